@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
 
+use crate::plan::{DeletionThreshold, Policy};
+
 /// Available DNS providers as subcommands, each with its own configuration options
 #[derive(Clone, Debug, Subcommand)]
 pub enum DnsProvider {
@@ -9,6 +11,36 @@ pub enum DnsProvider {
     HetznerCloud(HetznerCloudConfig),
 }
 
+/// The command to run, which by default keeps the DNS records in sync with the record sources
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Prints the changes needed to bring the DNS provider in line with the record sources and
+    /// exits, with exit code 2 if there are any.
+    Plan {
+        #[command(subcommand)]
+        dns_provider: DnsProvider,
+    },
+    /// Makes the changes needed to bring the DNS provider in line with the record sources once
+    /// and exits, with exit code 2 if there were any.
+    Apply {
+        #[command(subcommand)]
+        dns_provider: DnsProvider,
+    },
+    #[command(flatten)]
+    Run(DnsProvider),
+}
+
+impl Command {
+    /// Returns the configuration of the DNS provider the command uses
+    pub fn dns_provider(&self) -> &DnsProvider {
+        match self {
+            Command::Plan { dns_provider }
+            | Command::Apply { dns_provider }
+            | Command::Run(dns_provider) => dns_provider,
+        }
+    }
+}
+
 /// Define a struct to hold all command-line arguments
 #[derive(Clone, Debug, Parser)]
 #[command(author, about, version)]
@@ -17,8 +49,121 @@ pub struct Config {
     #[arg(long, env, default_value = "http://localhost:8500")]
     pub consul_address: url::Url,
 
+    /// Specifies the Consul datacenters whose services are published. Defaults to the datacenter
+    /// of the Consul agent.
+    #[arg(long, env, value_delimiter = ',')]
+    pub consul_datacenters: Vec<String>,
+
+    /// Specifies the Consul Enterprise namespace whose services are published, or `*` for all
+    /// namespaces. The lock and the state are kept in this namespace, or in the default namespace
+    /// for `*`.
+    #[arg(long, env)]
+    pub consul_namespace: Option<String>,
+
+    /// Specifies the Consul Enterprise admin partition.
+    #[arg(long, env)]
+    pub consul_partition: Option<String>,
+
+    /// Sets the Consul ACL token
+    #[arg(long, env = "CONSUL_HTTP_TOKEN", hide_env_values = true)]
+    pub consul_token: Option<String>,
+
+    /// Path to a file containing the Consul ACL token
+    #[arg(long, env = "CONSUL_HTTP_TOKEN_FILE", conflicts_with = "consul_token")]
+    pub consul_token_file: Option<std::path::PathBuf>,
+
+    /// Path to a PEM file with the CA certificates used to verify the Consul server.
+    #[arg(long, env = "CONSUL_CACERT")]
+    pub consul_ca_cert: Option<std::path::PathBuf>,
+
+    /// Path to a PEM file with the client certificate presented to Consul for mTLS.
+    #[arg(long, env = "CONSUL_CLIENT_CERT", requires = "consul_client_key")]
+    pub consul_client_cert: Option<std::path::PathBuf>,
+
+    /// Path to a PEM file with the private key of the client certificate.
+    #[arg(long, env = "CONSUL_CLIENT_KEY", requires = "consul_client_cert")]
+    pub consul_client_key: Option<std::path::PathBuf>,
+
+    /// Specifies the name the certificate of the Consul server is verified against, e.g.
+    /// `server.dc1.consul`, if it differs from the host of the Consul address.
+    #[arg(long, env = "CONSUL_TLS_SERVER_NAME")]
+    pub consul_tls_server_name: Option<String>,
+
+    /// Only publish DNS records for service instances whose Consul health checks are passing.
+    #[arg(long, env)]
+    pub consul_passing_only: bool,
+
+    /// Publishes the hostnames of Traefik `Host()` router rules of services tagged with
+    /// `traefik.enable=true`, pointing at this ingress address (`A`/`AAAA`) or hostname (`CNAME`).
+    #[arg(long, env)]
+    pub traefik_target: Option<String>,
+
+    /// Publishes the DNS records declared as JSON under this prefix in the Consul KV store, e.g.
+    /// `external-dns/records/`.
+    #[arg(long, env)]
+    pub consul_kv_prefix: Option<String>,
+
+    /// Publishes the hostnames declared by Consul ingress gateways, API gateways and their HTTP
+    /// routes, pointing at the instances of the respective gateway.
+    #[arg(long, env)]
+    pub consul_gateways: bool,
+
+    /// Specifies the address of the Nomad server. If set, the tags of services registered with
+    /// Nomad's native service discovery are published as well.
+    #[arg(long, env = "NOMAD_ADDR")]
+    pub nomad_address: Option<url::Url>,
+
+    /// Sets the Nomad ACL token
+    #[arg(long, env = "NOMAD_TOKEN", hide_env_values = true)]
+    pub nomad_token: Option<String>,
+
+    /// Path to a TOML file with static DNS records that are published in addition to the records
+    /// of the services.
+    #[arg(long, env)]
+    pub records_file: Option<std::path::PathBuf>,
+
+    /// Specifies how often the records file is checked for changes.
+    #[arg(long, env, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub records_file_poll_interval: std::time::Duration,
+
+    /// Restricts the changes made to the DNS provider: `sync` creates, updates and deletes
+    /// records, `upsert-only` never deletes records and `create-only` only creates records.
+    #[arg(long, env, value_enum, default_value_t = Policy::Sync)]
+    pub policy: Policy,
+
+    /// Refuses to apply changes deleting more than this number of records, e.g. `10`, or this
+    /// percentage of the managed records, e.g. `25%`, until the deletions drop below the threshold
+    /// or they are explicitly allowed.
+    #[arg(long, env)]
+    pub max_deletions: Option<DeletionThreshold>,
+
+    /// Allows changes to exceed `--max-deletions`. A single plan can also be allowed by setting
+    /// the Consul KV key `consul_external_dns/allow-mass-deletion` to `true`.
+    #[arg(long, env)]
+    pub allow_mass_deletion: bool,
+
+    /// Discovers the records and prints the changes to the DNS provider without making them or
+    /// writing to Consul.
+    #[arg(long, env)]
+    pub dry_run: bool,
+
+    /// Specifies how often the records in the DNS provider are compared with the stored state.
+    /// Records deleted or changed outside of this tool are then restored. Set to `0s` to disable.
+    #[arg(long, env, default_value = "5m", value_parser = humantime::parse_duration)]
+    pub resync_interval: std::time::Duration,
+
+    /// Enables the ownership registry: a companion TXT record with this owner ID is kept next to
+    /// every managed hostname. Hostnames claimed by another owner are never changed, and records
+    /// claimed by this owner are adopted even if the Consul state was lost.
+    #[arg(long, env)]
+    pub txt_owner_id: Option<String>,
+
+    /// Sets the prefix of the hostnames of the companion TXT records of the ownership registry.
+    #[arg(long, env, default_value = "_external-dns.")]
+    pub txt_prefix: String,
+
     #[command(subcommand)]
-    pub dns_provider: DnsProvider,
+    pub command: Command,
 }
 
 /// Define a struct to hold all command-line arguments
@@ -47,7 +192,7 @@ pub struct HetznerCloudConfig {
     #[arg(long, env = "HETZNER_CLOUD_API_TOKEN", hide_env_values = true)]
     pub(crate) api_token: String,
 
-    /// Sets the Hetzner Cloud DNS zone.
+    /// Sets the Hetzner Cloud DNS zone by its ID or name.
     #[arg(long, env = "HETZNER_CLOUD_DNS_ZONE")]
     pub(crate) dns_zone: String,
 
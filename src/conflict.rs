@@ -0,0 +1,87 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
+use tracing::warn;
+
+use crate::{consul::DnsRecord, dns_trait::DnsType};
+
+/// Resolves conflicting records declared for the same hostname.
+///
+/// A `CNAME` record can't coexist with any other record of its hostname, including other `CNAME`
+/// records. For such hostnames a single record wins: the one with the highest priority, then the
+/// one whose service already published the record, standing in for the oldest registration, and
+/// finally the one of the service with the lowest name, e.g. if both services show up in the same
+/// pass. If the winner is a `CNAME` record it's kept alone, otherwise all `CNAME` records of the
+/// hostname are dropped. Every dropped record is logged along with the service of the winner.
+///
+/// Only `CNAME` records are single-valued, the values of other types declared by several services
+/// are all published.
+pub fn resolve(desired: Vec<DnsRecord>, published: &HashMap<String, DnsRecord>) -> Vec<DnsRecord> {
+    let mut hostnames: BTreeMap<String, Vec<DnsRecord>> = BTreeMap::new();
+    for record in desired {
+        hostnames
+            .entry(record.hostname.clone())
+            .or_default()
+            .push(record);
+    }
+
+    let mut resolved = Vec::new();
+    for (hostname, records) in hostnames {
+        let cnames = records
+            .iter()
+            .filter(|record| record.type_ == DnsType::CNAME)
+            .count();
+        if cnames == 0 || records.len() == 1 {
+            resolved.extend(records);
+            continue;
+        }
+
+        let Some(winner) = records
+            .iter()
+            .min_by_key(|record| {
+                (
+                    Reverse(record.priority.unwrap_or_default()),
+                    !published.values().any(|p| p.same_identity(record)),
+                    record.service.clone(),
+                    record.type_.to_string(),
+                    record.value.clone(),
+                )
+            })
+            .cloned()
+        else {
+            continue;
+        };
+        let keep = |record: &DnsRecord| match winner.type_ {
+            DnsType::CNAME => *record == winner,
+            _ => record.type_ != DnsType::CNAME,
+        };
+
+        for record in records {
+            if keep(&record) {
+                resolved.push(record);
+                continue;
+            }
+            warn!(
+                "Conflicting DNS records for `{}`: dropping {} `{}` of {} in favor of {} `{}` of {}",
+                hostname,
+                record.type_,
+                record.value,
+                service_name(&record),
+                winner.type_,
+                winner.value,
+                service_name(&winner)
+            );
+        }
+    }
+    resolved
+}
+
+/// Names the service declaring a record for log messages
+fn service_name(record: &DnsRecord) -> String {
+    match &record.service {
+        Some(service) => format!("service `{}`", service),
+        None => "a service-less source".to_string(),
+    }
+}
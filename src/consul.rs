@@ -1,19 +1,37 @@
 use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
 use base64::prelude::{Engine as _, BASE64_STANDARD};
-use reqwest::{StatusCode, Url};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Certificate, Identity, StatusCode, Url,
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, future::Future, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 use tokio::{
-    task::JoinHandle,
+    task::{JoinHandle, JoinSet},
     time::{interval, MissedTickBehavior},
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::dns_trait::DnsType;
+use crate::{dns_trait::DnsType, record_source::RecordSource, template, traefik};
 
 const CONSUL_STORE_KEY: &str = "consul_external_dns/";
+/// Key below [`CONSUL_STORE_KEY`] allowing a single plan to exceed the deletion threshold
+const MASS_DELETION_OVERRIDE_KEY: &str = "allow-mass-deletion";
+/// Tag enabling External DNS for a service instance declaring its records in tags
+pub(crate) const TAG_ENABLE: &str = "external-dns.enable=true";
+/// Meta key enabling External DNS for a service instance declaring its records in its metadata
+const META_ENABLE_KEY: &str = "external-dns-enable";
+/// Namespace selecting the services of all namespaces
+const WILDCARD_NAMESPACE: &str = "*";
 
 #[derive(serde::Serialize)]
 struct CreateSessionRequest {
@@ -36,44 +54,282 @@ struct CreateSessionResponse {
 }
 
 /// A DNS record based on the tags of a service in Consul
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq)]
 pub struct DnsRecord {
     pub hostname: String,
     #[serde(rename = "type")]
     pub type_: DnsType,
     pub ttl: Option<i32>,
     pub value: String,
+    /// The service declaring the record, if any. Together with the hostname and type it
+    /// identifies a record whose value or TTL changed, but it's not part of the record's equality.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Decides which service keeps its records when several services declare conflicting records
+    /// for the same hostname, the highest priority wins. Like the service, it's not part of the
+    /// record's equality.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+impl DnsRecord {
+    /// Returns whether both records have the same hostname, type and service, i.e. one is an
+    /// updated version of the other.
+    pub fn same_identity(&self, other: &DnsRecord) -> bool {
+        self.hostname == other.hostname
+            && self.type_ == other.type_
+            && self.service == other.service
+    }
+}
+
+impl PartialEq for DnsRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.hostname == other.hostname
+            && self.type_ == other.type_
+            && self.ttl == other.ttl
+            && self.value == other.value
+    }
+}
+
+impl std::hash::Hash for DnsRecord {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.hostname.hash(state);
+        self.type_.hash(state);
+        self.ttl.hash(state);
+        self.value.hash(state);
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct ConsulKVResponse {
+    #[serde(rename = "Key", default)]
+    key: String,
     #[serde(rename = "Value")]
     value: Option<String>,
     #[serde(rename = "Session")]
     session: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct HealthServiceEntry {
+    #[serde(rename = "Node", default)]
+    node: HealthNode,
+    #[serde(rename = "Service")]
+    service: HealthService,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HealthNode {
+    #[serde(rename = "Node", default)]
+    name: String,
+    #[serde(rename = "Address", default)]
+    address: String,
+    #[serde(rename = "Datacenter", default)]
+    datacenter: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HealthService {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(rename = "Address", default)]
+    address: String,
+    #[serde(rename = "Namespace", default)]
+    namespace: Option<String>,
+    #[serde(rename = "Tags", default)]
+    tags: Vec<String>,
+    #[serde(rename = "Meta", default)]
+    meta: HashMap<String, String>,
+}
+
+/// Config entries declaring the hostnames served by gateways
+mod config_entry {
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct IngressGateway {
+        #[serde(rename = "Name")]
+        pub(super) name: String,
+        #[serde(rename = "Listeners", default)]
+        pub(super) listeners: Vec<IngressListener>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct IngressListener {
+        #[serde(rename = "Services", default)]
+        pub(super) services: Vec<IngressService>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct IngressService {
+        #[serde(rename = "Hosts", default)]
+        pub(super) hosts: Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct ApiGateway {
+        #[serde(rename = "Name")]
+        pub(super) name: String,
+        #[serde(rename = "Listeners", default)]
+        pub(super) listeners: Vec<ApiGatewayListener>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct ApiGatewayListener {
+        #[serde(rename = "Hostname", default)]
+        pub(super) hostname: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct HttpRoute {
+        #[serde(rename = "Hostnames", default)]
+        pub(super) hostnames: Vec<String>,
+        #[serde(rename = "Parents", default)]
+        pub(super) parents: Vec<RouteParent>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(super) struct RouteParent {
+        #[serde(rename = "Kind", default)]
+        pub(super) kind: String,
+        #[serde(rename = "Name")]
+        pub(super) name: String,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsulNamespace {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
 #[derive(Clone)]
 pub struct ConsulClient {
     pub http_client: reqwest::Client,
     pub kv_api_base_url: Url,
     pub catalog_api_base_url: Url,
+    pub health_api_base_url: Url,
+    pub config_api_base_url: Url,
+    pub namespaces_api_url: Url,
     pub session_api_base_url: Url,
     pub datacenter: Option<String>,
+    /// The Consul Enterprise namespace, or `*` for all namespaces.
+    pub namespace: Option<String>,
+    /// The Consul Enterprise admin partition.
+    pub partition: Option<String>,
+    /// Only consider service instances whose health checks are all passing.
+    pub passing_only: bool,
+    /// Publish the hostnames of Traefik `Host()` rules pointing at this address or hostname.
+    pub traefik_target: Option<String>,
+}
+
+/// Options for authenticating against Consul and connecting to it via TLS
+#[derive(Clone, Debug, Default)]
+pub struct ConsulHttpOptions {
+    /// ACL token sent with every request
+    pub token: Option<String>,
+    /// PEM file with the CA certificates used to verify the Consul server
+    pub ca_cert: Option<PathBuf>,
+    /// PEM files with the client certificate and its private key for mTLS
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    /// Name the certificate of the Consul server is verified against instead of the host of
+    /// its address
+    pub tls_server_name: Option<String>,
+}
+
+impl ConsulHttpOptions {
+    /// Builds the HTTP client for these options. If a TLS server name is set, the returned
+    /// address uses it as host while still connecting to the addresses of the original host.
+    fn build_client(&self, consul_address: Url) -> Result<(reqwest::Client, Url)> {
+        let mut builder = reqwest::Client::builder();
+        let mut consul_address = consul_address;
+
+        if let Some(token) = &self.token {
+            let mut token = HeaderValue::from_str(token).context("Invalid Consul ACL token")?;
+            token.set_sensitive(true);
+            builder = builder.default_headers(HeaderMap::from_iter([(
+                HeaderName::from_static("x-consul-token"),
+                token,
+            )]));
+        }
+
+        if let Some(path) = &self.ca_cert {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+            for cert in Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let Some((cert_path, key_path)) = &self.client_cert {
+            let mut pem = std::fs::read(cert_path).with_context(|| {
+                format!("Failed to read client certificate {}", cert_path.display())
+            })?;
+            pem.extend(
+                std::fs::read(key_path)
+                    .with_context(|| format!("Failed to read client key {}", key_path.display()))?,
+            );
+            builder = builder.identity(Identity::from_pem(&pem)?);
+        }
+
+        if let Some(server_name) = &self.tls_server_name {
+            let addrs: Vec<SocketAddr> = consul_address
+                .socket_addrs(|| None)
+                .context("Failed to resolve the Consul address")?;
+            builder = builder.resolve_to_addrs(server_name, &addrs);
+            consul_address
+                .set_host(Some(server_name))
+                .context("Invalid Consul TLS server name")?;
+        }
+
+        Ok((builder.build()?, consul_address))
+    }
 }
 
 impl ConsulClient {
-    pub fn new(consul_address: Url, consul_datacenter: Option<String>) -> Result<ConsulClient> {
+    pub fn new(
+        consul_address: Url,
+        consul_datacenter: Option<String>,
+        passing_only: bool,
+        traefik_target: Option<String>,
+    ) -> Result<ConsulClient> {
+        Self::with_options(
+            consul_address,
+            consul_datacenter,
+            passing_only,
+            traefik_target,
+            &ConsulHttpOptions::default(),
+        )
+    }
+
+    /// Creates a client authenticating and connecting to Consul as specified by `options`
+    pub fn with_options(
+        consul_address: Url,
+        consul_datacenter: Option<String>,
+        passing_only: bool,
+        traefik_target: Option<String>,
+        options: &ConsulHttpOptions,
+    ) -> Result<ConsulClient> {
+        let (client, consul_address) = options.build_client(consul_address)?;
         let kv_api_base_url = consul_address.join("v1/")?.join("kv/")?;
         let catalog_api_base_url = consul_address.join("v1/")?.join("catalog/")?;
+        let health_api_base_url = consul_address.join("v1/")?.join("health/")?;
+        let config_api_base_url = consul_address.join("v1/")?.join("config/")?;
+        let namespaces_api_url = consul_address.join("v1/")?.join("namespaces")?;
         let session_api_base_url = consul_address.join("v1/")?.join("session/")?;
-        let client = reqwest::Client::new();
         Ok(ConsulClient {
             http_client: client,
             kv_api_base_url,
             catalog_api_base_url,
+            health_api_base_url,
+            config_api_base_url,
+            namespaces_api_url,
             session_api_base_url,
             datacenter: consul_datacenter,
+            namespace: None,
+            partition: None,
+            passing_only,
+            traefik_target,
         })
     }
 
@@ -90,8 +346,7 @@ impl ConsulClient {
         let session_url = self.session_api_base_url.join("create")?;
 
         let resp = self
-            .http_client
-            .put(session_url)
+            .scoped(self.http_client.put(session_url))
             .json(&session_request)
             .send()
             .await?
@@ -122,7 +377,7 @@ impl ConsulClient {
         loop {
             let lock_url = self.kv_api_base_url.join(CONSUL_STORE_KEY)?.join("lock")?;
 
-            let mut req = self.http_client.put(lock_url);
+            let mut req = self.scoped(self.http_client.put(lock_url));
             req = req.query(&[("acquire", &session_id.to_string())]);
 
             let resp = req.send().await?;
@@ -148,7 +403,7 @@ impl ConsulClient {
         loop {
             let lock_url = self.kv_api_base_url.join(CONSUL_STORE_KEY)?.join("lock")?;
 
-            let mut req = self.http_client.get(lock_url);
+            let mut req = self.scoped(self.http_client.get(lock_url));
 
             if let Some(index) = consul_index.take() {
                 req = req.query(&[("index", &index)]);
@@ -174,14 +429,148 @@ impl ConsulClient {
         }
     }
 
-    /// Retrieves a list of all registered services and parses their tags into DnsTag
+    /// Retrieves all service instances enabled for External DNS and parses their tags and
+    /// metadata into DnsTag
+    ///
+    /// Tags and metadata are parsed per instance, so every instance contributes its own records.
+    /// If `passing_only` is set, only instances whose health checks are passing are taken into
+    /// account, so records are withdrawn once an instance goes critical.
     pub async fn fetch_service_tags(
         &self,
         consul_index: &mut Option<String>,
     ) -> Result<Vec<DnsRecord>> {
+        if consul_index.is_some() {
+            if self.passing_only {
+                // Health check changes don't bump the index of the service catalog, so we have to
+                // watch both the catalog and the health checks for changes.
+                self.wait_for_service_or_health_changes(consul_index)
+                    .await?;
+            } else {
+                let urls = vec![self.catalog_api_base_url.join("services")?];
+                self.wait_for_changes(urls, consul_index).await?;
+            }
+        }
+
+        let mut dns_tags = HashSet::new();
+        for client in self.namespace_clients().await? {
+            let mut catalog_index = None;
+            let services = client.fetch_services(&mut catalog_index).await?;
+            *consul_index = max_consul_index(consul_index.take(), catalog_index);
+
+            for service_name in services.into_keys() {
+                dns_tags.extend(client.fetch_service_records(&service_name).await?);
+            }
+        }
+
+        Ok(dns_tags.into_iter().collect())
+    }
+
+    /// Parses the tags and metadata of all instances of a service into DNS records
+    async fn fetch_service_records(&self, service_name: &str) -> Result<Vec<DnsRecord>> {
+        let instances = self.fetch_service_instances(service_name).await?;
+        if instances.is_empty() {
+            info!(
+                "Service `{}` has no eligible instances, skipping its DNS records",
+                service_name
+            );
+            return Ok(Vec::new());
+        }
+
+        // Order the instances so that `{{index}}` is stable across queries
+        let mut instances = instances;
+        instances.sort_by(|a, b| (&a.node.name, &a.service.id).cmp(&(&b.node.name, &b.service.id)));
+
+        let mut dns_tags = Vec::new();
+        for (
+            index,
+            HealthServiceEntry {
+                node,
+                service: instance,
+            },
+        ) in instances.into_iter().enumerate()
+        {
+            // Hostnames may be templates referencing attributes of the instance
+            let datacenter = if node.datacenter.is_empty() {
+                self.datacenter.clone().unwrap_or_default()
+            } else {
+                node.datacenter
+            };
+            let namespace = match instance.namespace {
+                Some(namespace) => namespace,
+                None => self.namespace.clone().unwrap_or("default".to_string()),
+            };
+            let mut variables = HashMap::from([
+                ("service".to_string(), service_name.to_string()),
+                ("node".to_string(), node.name),
+                ("dc".to_string(), datacenter),
+                ("namespace".to_string(), namespace),
+                ("index".to_string(), index.to_string()),
+            ]);
+            variables.extend(
+                instance
+                    .meta
+                    .iter()
+                    .map(|(key, value)| (format!("meta.{}", key), value.clone())),
+            );
+
+            let mut records = Vec::new();
+            if instance.tags.iter().any(|tag| tag == TAG_ENABLE) {
+                records.extend(parse_dns_tags(instance.tags.clone()));
+            }
+            if instance.meta.get(META_ENABLE_KEY).map(String::as_str) == Some("true") {
+                records.extend(parse_dns_meta(instance.meta));
+            }
+            dns_tags.extend(template::render_hostnames(records, &variables));
+
+            if let Some(target) = &self.traefik_target {
+                if instance.tags.iter().any(|tag| tag == traefik::TAG_ENABLE) {
+                    dns_tags.extend(traefik::parse_traefik_tags(&instance.tags, target));
+                }
+            }
+        }
+
+        for record in &mut dns_tags {
+            record.service = Some(service_name.to_string());
+        }
+
+        Ok(dns_tags)
+    }
+
+    /// Returns clients scoped to each namespace whose services should be published. For the
+    /// wildcard namespace `*` these are all namespaces of the partition.
+    async fn namespace_clients(&self) -> Result<Vec<ConsulClient>> {
+        if self.namespace.as_deref() != Some(WILDCARD_NAMESPACE) {
+            return Ok(vec![self.clone()]);
+        }
+
+        let namespaces = self
+            .scoped(self.http_client.get(self.namespaces_api_url.clone()))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<ConsulNamespace>>()
+            .await?;
+
+        Ok(namespaces
+            .into_iter()
+            .map(|namespace| ConsulClient {
+                namespace: Some(namespace.name),
+                ..self.clone()
+            })
+            .collect())
+    }
+
+    /// Fetches the names and tags of all services enabled for External DNS from the catalog.
+    ///
+    /// If `consul_index` is set, this is a blocking query that only returns once the catalog has
+    /// changed since that index.
+    async fn fetch_services(
+        &self,
+        consul_index: &mut Option<String>,
+    ) -> Result<HashMap<String, Vec<String>>> {
         let services_url = self.catalog_api_base_url.join("services")?;
 
-        let mut req = self.http_client.get(services_url);
+        let mut req = self.catalog_get(services_url);
 
         if let Some(index) = consul_index {
             req = req.query(&[("index", &index.to_string())]);
@@ -190,7 +579,10 @@ impl ConsulClient {
         // Add a filter to only match "normal" Consul services
         req = req.query(&[(
             "filter",
-            r#"ServiceKind == "" and ServiceTags contains "external-dns.enable=true""#,
+            format!(
+                r#"ServiceKind == "" and ({})"#,
+                self.enable_filter("ServiceTags", "ServiceMeta")
+            ),
         )]);
 
         let response = req.send().await?.error_for_status()?;
@@ -203,14 +595,282 @@ impl ConsulClient {
             }
         }
 
-        let records = response.json::<HashMap<String, Vec<String>>>().await?;
+        Ok(response.json::<HashMap<String, Vec<String>>>().await?)
+    }
 
-        let dns_tags = records
-            .into_iter()
-            .flat_map(|(_service_name, tags)| parse_dns_tags(tags))
-            .collect();
+    /// Blocks until either the service catalog or the state of any health check has changed
+    /// since `consul_index`.
+    async fn wait_for_service_or_health_changes(
+        &self,
+        consul_index: &mut Option<String>,
+    ) -> Result<()> {
+        let urls = vec![
+            self.catalog_api_base_url.join("services")?,
+            self.health_api_base_url.join("state/any")?,
+        ];
+        self.wait_for_changes(urls, consul_index).await
+    }
 
-        Ok(dns_tags)
+    /// Issues blocking queries against all given endpoints and returns as soon as any of them
+    /// has changed since `consul_index`.
+    async fn wait_for_changes(
+        &self,
+        urls: Vec<Url>,
+        consul_index: &mut Option<String>,
+    ) -> Result<()> {
+        // All queries are blocking on the same Raft index, so whichever returns first tells us
+        // that something has changed. The remaining queries are aborted once the set is dropped.
+        let mut queries = JoinSet::new();
+        for url in urls {
+            let mut req = self.catalog_get(url);
+            if let Some(index) = consul_index {
+                req = req.query(&[("index", &index.to_string())]);
+            }
+            queries.spawn(async move {
+                let response = req.send().await?.error_for_status()?;
+                Ok::<_, reqwest::Error>(
+                    response
+                        .headers()
+                        .get("X-Consul-Index")
+                        .and_then(|value| value.to_str().ok())
+                        .map(|value| value.to_string()),
+                )
+            });
+        }
+
+        let Some(new_index) = queries.join_next().await else {
+            return Ok(());
+        };
+
+        *consul_index = max_consul_index(consul_index.take(), new_index??);
+        Ok(())
+    }
+
+    /// Builds a GET request for the catalog, health or config APIs, scoped to the datacenter,
+    /// partition and namespace of this client. The KV store and sessions are always used in the
+    /// local datacenter.
+    fn catalog_get(&self, url: Url) -> reqwest::RequestBuilder {
+        let mut req = self.http_client.get(url);
+        if let Some(datacenter) = &self.datacenter {
+            req = req.query(&[("dc", datacenter)]);
+        }
+        if let Some(partition) = &self.partition {
+            req = req.query(&[("partition", partition)]);
+        }
+        if let Some(namespace) = &self.namespace {
+            req = req.query(&[("ns", namespace)]);
+        }
+        req
+    }
+
+    /// Scopes a request for the KV store or sessions to the namespace and partition of this
+    /// client. The wildcard namespace is only meaningful for catalog queries, so the default
+    /// namespace is used for it instead.
+    fn scoped(&self, mut req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(partition) = &self.partition {
+            req = req.query(&[("partition", partition)]);
+        }
+        match self.namespace.as_deref() {
+            Some(WILDCARD_NAMESPACE) | None => req,
+            Some(namespace) => req.query(&[("ns", namespace)]),
+        }
+    }
+
+    /// Builds a filter expression matching service instances enabled for External DNS, given the
+    /// selectors of the tags and metadata of the queried endpoint.
+    fn enable_filter(&self, tags_selector: &str, meta_selector: &str) -> String {
+        let mut filter = format!(
+            r#"{tags_selector} contains "{TAG_ENABLE}" or {meta_selector}["{META_ENABLE_KEY}"] == "true""#
+        );
+        if self.traefik_target.is_some() {
+            filter.push_str(&format!(
+                r#" or {tags_selector} contains "{}""#,
+                traefik::TAG_ENABLE
+            ));
+        }
+        filter
+    }
+
+    /// Fetches all instances of a service that are enabled for External DNS. If `passing_only`
+    /// is set, only instances whose health checks are passing are returned.
+    async fn fetch_service_instances(&self, service_name: &str) -> Result<Vec<HealthServiceEntry>> {
+        let filter = self.enable_filter("Service.Tags", "Service.Meta");
+        self.fetch_health_entries(service_name, Some(filter)).await
+    }
+
+    /// Fetches all instances of a service and their nodes, optionally matching a filter. If
+    /// `passing_only` is set, only instances whose health checks are passing are returned.
+    async fn fetch_health_entries(
+        &self,
+        service_name: &str,
+        filter: Option<String>,
+    ) -> Result<Vec<HealthServiceEntry>> {
+        let mut url = self.health_api_base_url.join("service/")?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Consul API url"))?
+            .pop_if_empty()
+            .push(service_name);
+
+        let mut req = self.catalog_get(url);
+        if let Some(filter) = filter {
+            req = req.query(&[("filter", filter)]);
+        }
+        if self.passing_only {
+            req = req.query(&[("passing", "true")]);
+        }
+
+        Ok(req
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<HealthServiceEntry>>()
+            .await?)
+    }
+
+    /// Retrieves the hostnames declared by ingress gateways, API gateways and their HTTP routes
+    /// and builds DNS records pointing them at the instances of the respective gateway.
+    ///
+    /// If `consul_index` is set, this blocks until the config entries or the gateway instances
+    /// may have changed since that index.
+    pub async fn fetch_gateway_records(
+        &self,
+        consul_index: &mut Option<String>,
+    ) -> Result<Vec<DnsRecord>> {
+        if consul_index.is_some() {
+            let mut urls = vec![
+                self.config_api_base_url.join("ingress-gateway")?,
+                self.config_api_base_url.join("api-gateway")?,
+                self.config_api_base_url.join("http-route")?,
+                self.catalog_api_base_url.join("services")?,
+            ];
+            if self.passing_only {
+                urls.push(self.health_api_base_url.join("state/any")?);
+            }
+            self.wait_for_changes(urls, consul_index).await?;
+        }
+
+        let mut records = HashSet::new();
+        for client in self.namespace_clients().await? {
+            records.extend(client.fetch_namespace_gateway_records(consul_index).await?);
+        }
+
+        Ok(records.into_iter().collect())
+    }
+
+    /// Retrieves the hostnames declared by the gateways in the namespace of this client and builds
+    /// DNS records pointing them at the instances of the respective gateway.
+    async fn fetch_namespace_gateway_records(
+        &self,
+        consul_index: &mut Option<String>,
+    ) -> Result<Vec<DnsRecord>> {
+        // Collect the hostnames declared for each gateway
+        let mut gateway_hosts: HashMap<String, HashSet<String>> = HashMap::new();
+
+        let ingress_gateways: Vec<config_entry::IngressGateway> = self
+            .fetch_config_entries("ingress-gateway", consul_index)
+            .await?;
+        for gateway in ingress_gateways {
+            let hosts = gateway_hosts.entry(gateway.name).or_default();
+            hosts.extend(
+                gateway
+                    .listeners
+                    .into_iter()
+                    .flat_map(|listener| listener.services)
+                    .flat_map(|service| service.hosts),
+            );
+        }
+
+        let api_gateways: Vec<config_entry::ApiGateway> = self
+            .fetch_config_entries("api-gateway", consul_index)
+            .await?;
+        for gateway in api_gateways {
+            let hosts = gateway_hosts.entry(gateway.name).or_default();
+            hosts.extend(
+                gateway
+                    .listeners
+                    .into_iter()
+                    .map(|listener| listener.hostname)
+                    .filter(|hostname| !hostname.is_empty()),
+            );
+        }
+
+        let http_routes: Vec<config_entry::HttpRoute> = self
+            .fetch_config_entries("http-route", consul_index)
+            .await?;
+        for route in http_routes {
+            for parent in route.parents {
+                if !parent.kind.is_empty() && parent.kind != "api-gateway" {
+                    continue;
+                }
+                gateway_hosts
+                    .entry(parent.name)
+                    .or_default()
+                    .extend(route.hostnames.iter().cloned());
+            }
+        }
+
+        let mut records = HashSet::new();
+        for (gateway, hosts) in gateway_hosts {
+            // A host of `*` matches any hostname and can't be published
+            let hosts: Vec<String> = hosts.into_iter().filter(|host| host != "*").collect();
+            if hosts.is_empty() {
+                continue;
+            }
+
+            let instances = self.fetch_health_entries(&gateway, None).await?;
+            if instances.is_empty() {
+                info!(
+                    "Gateway `{}` has no eligible instances, skipping its DNS records",
+                    gateway
+                );
+                continue;
+            }
+
+            for instance in instances {
+                let address = if instance.service.address.is_empty() {
+                    instance.node.address
+                } else {
+                    instance.service.address
+                };
+                let type_ = match address.parse::<IpAddr>() {
+                    Ok(IpAddr::V4(_)) => DnsType::A,
+                    Ok(IpAddr::V6(_)) => DnsType::AAAA,
+                    Err(_) => DnsType::CNAME,
+                };
+                records.extend(hosts.iter().map(|hostname| DnsRecord {
+                    hostname: hostname.clone(),
+                    type_: type_.clone(),
+                    ttl: None,
+                    value: address.clone(),
+                    service: Some(gateway.clone()),
+                    priority: None,
+                }));
+            }
+        }
+
+        Ok(records.into_iter().collect())
+    }
+
+    /// Fetches all config entries of the given kind, keeping track of the most recent index.
+    async fn fetch_config_entries<T: for<'de> Deserialize<'de>>(
+        &self,
+        kind: &str,
+        consul_index: &mut Option<String>,
+    ) -> Result<Vec<T>> {
+        let response = self
+            .catalog_get(self.config_api_base_url.join(kind)?)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let index = response
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        *consul_index = max_consul_index(consul_index.take(), index);
+
+        Ok(response.json().await?)
     }
 
     // Store all DNS records under a single key as a HashMap
@@ -220,8 +880,7 @@ impl ConsulClient {
     ) -> Result<()> {
         let url = self.kv_api_base_url.join(CONSUL_STORE_KEY)?.join("state")?;
 
-        self.http_client
-            .put(url)
+        self.scoped(self.http_client.put(url))
             .json(&dns_state)
             .send()
             .await?
@@ -229,12 +888,47 @@ impl ConsulClient {
         Ok(())
     }
 
+    /// Returns whether the override allowing plans to exceed the deletion threshold is set in the
+    /// KV store, i.e. whether `consul_external_dns/allow-mass-deletion` is `true`.
+    pub async fn mass_deletion_allowed(&self) -> Result<bool> {
+        let url = self
+            .kv_api_base_url
+            .join(CONSUL_STORE_KEY)?
+            .join(MASS_DELETION_OVERRIDE_KEY)?;
+
+        let resp = self
+            .scoped(self.http_client.get(url))
+            .query(&[("raw", "true")])
+            .send()
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        Ok(resp.error_for_status()?.text().await?.trim() == "true")
+    }
+
+    /// Removes the override allowing plans to exceed the deletion threshold from the KV store, so
+    /// it only applies to a single plan.
+    pub async fn clear_mass_deletion_override(&self) -> Result<()> {
+        let url = self
+            .kv_api_base_url
+            .join(CONSUL_STORE_KEY)?
+            .join(MASS_DELETION_OVERRIDE_KEY)?;
+
+        self.scoped(self.http_client.delete(url))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
     /// Fetches all DNS records from Consul.
     /// This function retrieves the state of all DNS records stored under a specific Consul key.
     pub async fn fetch_all_dns_records(&self) -> Result<HashMap<String, DnsRecord>> {
         let url = self.kv_api_base_url.join(CONSUL_STORE_KEY)?.join("state")?;
 
-        let resp = self.http_client.get(url).send().await?;
+        let resp = self.scoped(self.http_client.get(url)).send().await?;
 
         if !resp.status().is_success() {
             if resp.status() == StatusCode::NOT_FOUND {
@@ -268,9 +962,167 @@ impl ConsulClient {
 
         Ok(records)
     }
+
+    /// Fetches the DNS records declared under a KV prefix, where every key holds a JSON object or
+    /// an array of JSON objects with the fields of a record.
+    ///
+    /// If `consul_index` is set, this is a blocking query that only returns once a key under the
+    /// prefix has changed since that index.
+    pub async fn fetch_kv_records(
+        &self,
+        prefix: &str,
+        consul_index: &mut Option<String>,
+    ) -> Result<Vec<DnsRecord>> {
+        let url = self.kv_api_base_url.join(prefix.trim_start_matches('/'))?;
+
+        let mut req = self
+            .scoped(self.http_client.get(url))
+            .query(&[("recurse", "true")]);
+
+        if let Some(index) = consul_index {
+            req = req.query(&[("index", &index.to_string())]);
+        }
+
+        let resp = req.send().await?;
+
+        if let Some(index_header) = resp.headers().get("X-Consul-Index") {
+            if let Ok(index_str) = index_header.to_str() {
+                let _ = consul_index.insert(index_str.to_string());
+            } else {
+                error!("Failed to convert X-Consul-Index header to string");
+            }
+        }
+
+        // Consul responds with a 404 if there are no keys under the prefix
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let kv_response = resp
+            .error_for_status()?
+            .json::<Vec<ConsulKVResponse>>()
+            .await?;
+
+        let mut records = HashSet::new();
+        for entry in kv_response {
+            let Some(encoded_value) = entry.value else {
+                continue;
+            };
+            let decoded_bytes = match BASE64_STANDARD.decode(encoded_value) {
+                Ok(decoded_bytes) => decoded_bytes,
+                Err(e) => {
+                    error!("Failed to decode value of key {}: {}", entry.key, e);
+                    continue;
+                }
+            };
+            match serde_json::from_slice::<KvRecords>(&decoded_bytes) {
+                Ok(KvRecords::One(record)) => {
+                    records.insert(record);
+                }
+                Ok(KvRecords::Many(key_records)) => records.extend(key_records),
+                Err(e) => error!("Failed to parse DNS records in key {}: {}", entry.key, e),
+            }
+        }
+
+        Ok(records.into_iter().collect())
+    }
+}
+
+/// The DNS records declared in a single key under the records KV prefix
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KvRecords {
+    One(DnsRecord),
+    Many(Vec<DnsRecord>),
+}
+
+/// A record source reading DNS records declared under a prefix in the Consul KV store
+pub struct ConsulKvSource {
+    client: ConsulClient,
+    prefix: String,
+    consul_index: Option<String>,
+}
+
+impl ConsulKvSource {
+    pub fn new(client: ConsulClient, prefix: String) -> Self {
+        Self {
+            client,
+            prefix,
+            consul_index: None,
+        }
+    }
+}
+
+#[async_trait]
+impl RecordSource for ConsulKvSource {
+    async fn fetch_records(&mut self) -> Result<Vec<DnsRecord>> {
+        self.client
+            .fetch_kv_records(&self.prefix, &mut self.consul_index)
+            .await
+    }
+}
+
+/// A record source reading the hostnames declared by Consul ingress and API gateways
+pub struct ConsulGatewaySource {
+    client: ConsulClient,
+    consul_index: Option<String>,
+}
+
+impl ConsulGatewaySource {
+    pub fn new(client: ConsulClient) -> Self {
+        Self {
+            client,
+            consul_index: None,
+        }
+    }
+}
+
+#[async_trait]
+impl RecordSource for ConsulGatewaySource {
+    async fn fetch_records(&mut self) -> Result<Vec<DnsRecord>> {
+        self.client
+            .fetch_gateway_records(&mut self.consul_index)
+            .await
+    }
+}
+
+/// A record source reading the tags and metadata of the services registered in Consul
+pub struct ConsulServiceSource {
+    client: ConsulClient,
+    consul_index: Option<String>,
+}
+
+impl ConsulServiceSource {
+    pub fn new(client: ConsulClient) -> Self {
+        Self {
+            client,
+            consul_index: None,
+        }
+    }
+}
+
+#[async_trait]
+impl RecordSource for ConsulServiceSource {
+    async fn fetch_records(&mut self) -> Result<Vec<DnsRecord>> {
+        self.client.fetch_service_tags(&mut self.consul_index).await
+    }
+}
+
+/// Returns the more recent of two `X-Consul-Index` values.
+///
+/// Consul may return an index lower than the one a blocking query was started with (e.g. when the
+/// watched table didn't change before the wait time elapsed), in which case we keep the old one.
+fn max_consul_index(a: Option<String>, b: Option<String>) -> Option<String> {
+    let parse = |index: &Option<String>| index.as_ref().and_then(|i| i.parse::<u64>().ok());
+    match (parse(&a), parse(&b)) {
+        (Some(x), Some(y)) if x >= y => a,
+        (Some(_), Some(_)) => b,
+        (Some(_), None) => a,
+        _ => b.or(a),
+    }
 }
 
-fn parse_dns_tags(tags: Vec<String>) -> Vec<DnsRecord> {
+pub(crate) fn parse_dns_tags(tags: Vec<String>) -> Vec<DnsRecord> {
     const PREFIX: &str = "external-dns.";
     // Parse service tags of the format `external-dns.<identifier>.<field>=<value>`.
     let mut dns_tags: HashMap<String, HashMap<String, String>> = HashMap::new();
@@ -290,55 +1142,155 @@ fn parse_dns_tags(tags: Vec<String>) -> Vec<DnsRecord> {
             .insert(field.to_string(), value.to_string());
     }
 
+    dns_tags
+        .into_iter()
+        .filter_map(|(identifier, fields)| parse_dns_fields(&identifier, fields))
+        .collect()
+}
+
+/// Parses the service metadata of an instance into DNS records.
+///
+/// A single record can be declared with the `external-dns-<field>` keys, e.g.
+/// `external-dns-hostname`, while the `external-dns` key can hold a JSON object or an array of
+/// JSON objects with the same fields to declare several records.
+fn parse_dns_meta(mut meta: HashMap<String, String>) -> Vec<DnsRecord> {
+    const PREFIX: &str = "external-dns-";
+    const JSON_KEY: &str = "external-dns";
+
     let mut records = Vec::new();
-    for (identifier, mut tags) in dns_tags {
-        let Some(hostname) = tags.remove("hostname") else {
-            error!("Missing hostname for identifier: {}", identifier);
-            continue;
-        };
 
-        let type_string = tags.remove("type");
-        let type_: DnsType = match type_string.as_ref().map(|t| t.parse()) {
-            None => {
-                error!("Missing type for identifier: {}", identifier);
-                continue;
-            }
-            Some(Ok(t)) => t,
-            Some(Err(e)) => {
+    if let Some(json) = meta.remove(JSON_KEY) {
+        let entries = match serde_json::from_str::<serde_json::Value>(&json) {
+            Ok(serde_json::Value::Array(entries)) => entries,
+            Ok(entry @ serde_json::Value::Object(_)) => vec![entry],
+            Ok(_) => {
                 error!(
-                    "Unsupported record type {} specified for identifier {}: {}",
-                    type_string.unwrap_or_default(),
-                    identifier,
-                    e
+                    "Expected a JSON object or array in the `{}` meta key",
+                    JSON_KEY
                 );
-                continue;
+                vec![]
+            }
+            Err(e) => {
+                error!("Failed to parse the `{}` meta key: {}", JSON_KEY, e);
+                vec![]
             }
         };
 
-        let ttl = match tags.remove("ttl").map(|t| t.parse()) {
-            None => None,
-            Some(Ok(ttl)) => Some(ttl),
-            Some(Err(e)) => {
-                error!("Failed to parse TTL for identifier {}: {}", identifier, e);
+        for (index, entry) in entries.into_iter().enumerate() {
+            let identifier = format!("{}[{}]", JSON_KEY, index);
+            let serde_json::Value::Object(entry) = entry else {
+                error!("Expected a JSON object for identifier: {}", identifier);
                 continue;
-            }
-        };
-        let Some(value) = tags.remove("value") else {
-            error!("Missing value for identifier: {}", identifier);
-            continue;
-        };
+            };
+            let fields = entry
+                .into_iter()
+                .map(|(field, value)| match value {
+                    serde_json::Value::String(value) => (field, value),
+                    value => (field, value.to_string()),
+                })
+                .collect();
+            records.extend(parse_dns_fields(&identifier, fields));
+        }
+    }
 
-        records.push(DnsRecord {
-            hostname,
-            type_,
-            ttl,
-            value,
-        });
+    let fields: HashMap<String, String> = meta
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.strip_prefix(PREFIX)?.to_string(), value)))
+        .collect();
+    // `external-dns-enable` alone only enables the service without declaring a record
+    if fields.keys().any(|field| field != "enable") {
+        records.extend(parse_dns_fields(JSON_KEY, fields));
     }
 
     records
 }
 
+/// Builds a DNS record from the fields declared for an identifier, logging why it is skipped if
+/// the fields are incomplete or invalid.
+fn parse_dns_fields(identifier: &str, mut fields: HashMap<String, String>) -> Option<DnsRecord> {
+    const KNOWN_FIELDS: &[&str] = &["hostname", "type", "ttl", "value", "enable", "priority"];
+
+    for field in fields.keys() {
+        if !KNOWN_FIELDS.contains(&field.as_str()) {
+            warn!(
+                "Ignoring unknown field `{}` for identifier: {}",
+                field, identifier
+            );
+        }
+    }
+
+    match fields.remove("enable").map(|e| e.parse::<bool>()) {
+        None | Some(Ok(true)) => {}
+        Some(Ok(false)) => {
+            debug!("DNS record disabled for identifier: {}", identifier);
+            return None;
+        }
+        Some(Err(e)) => {
+            error!(
+                "Failed to parse enable flag for identifier {}: {}",
+                identifier, e
+            );
+            return None;
+        }
+    }
+
+    let Some(hostname) = fields.remove("hostname") else {
+        error!("Missing hostname for identifier: {}", identifier);
+        return None;
+    };
+
+    let type_string = fields.remove("type");
+    let type_: DnsType = match type_string.as_ref().map(|t| t.parse()) {
+        None => {
+            error!("Missing type for identifier: {}", identifier);
+            return None;
+        }
+        Some(Ok(t)) => t,
+        Some(Err(e)) => {
+            error!(
+                "Unsupported record type {} specified for identifier {}: {}",
+                type_string.unwrap_or_default(),
+                identifier,
+                e
+            );
+            return None;
+        }
+    };
+
+    let ttl = match fields.remove("ttl").map(|t| t.parse()) {
+        None => None,
+        Some(Ok(ttl)) => Some(ttl),
+        Some(Err(e)) => {
+            error!("Failed to parse TTL for identifier {}: {}", identifier, e);
+            return None;
+        }
+    };
+    let Some(value) = fields.remove("value") else {
+        error!("Missing value for identifier: {}", identifier);
+        return None;
+    };
+    let priority = match fields.remove("priority").map(|p| p.parse()) {
+        None => None,
+        Some(Ok(priority)) => Some(priority),
+        Some(Err(e)) => {
+            error!(
+                "Failed to parse priority for identifier {}: {}",
+                identifier, e
+            );
+            return None;
+        }
+    };
+
+    Some(DnsRecord {
+        hostname,
+        type_,
+        ttl,
+        value,
+        service: None,
+        priority,
+    })
+}
+
 pub struct ConsulSession {
     pub session_id: Uuid,
     pub join_handle: JoinHandle<()>,
@@ -374,8 +1326,7 @@ fn session_handler(
 
             info!("Renewing Consul session");
             let res: std::result::Result<_, _> = client
-                .http_client
-                .put(renewal_url.clone())
+                .scoped(client.http_client.put(renewal_url.clone()))
                 .send()
                 .await
                 .and_then(|res| res.error_for_status());
@@ -388,8 +1339,7 @@ fn session_handler(
 
         info!("Destroying Consul session");
         let res = client
-            .http_client
-            .put(destroy_url)
+            .scoped(client.http_client.put(destroy_url))
             .send()
             .await
             .and_then(|res| res.error_for_status());
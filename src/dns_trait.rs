@@ -1,10 +1,10 @@
 use anyhow::Result;
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
-use crate::consul;
+use crate::{consul, plan::Plan};
 
 // Define an error type for unsupported DNS record types
 #[derive(Debug, Clone)]
@@ -17,11 +17,13 @@ impl Display for UnsupportedRecordType {
 }
 
 // convert dnstag type to an enum
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum DnsType {
     A,
     AAAA,
     CNAME,
+    MX,
+    TXT,
 }
 
 // implement FromStr for DnsType
@@ -33,6 +35,8 @@ impl std::str::FromStr for DnsType {
             "A" => Ok(DnsType::A),
             "AAAA" => Ok(DnsType::AAAA),
             "CNAME" => Ok(DnsType::CNAME),
+            "MX" => Ok(DnsType::MX),
+            "TXT" => Ok(DnsType::TXT),
             _ => Err(UnsupportedRecordType(s.to_string())),
         }
     }
@@ -45,15 +49,40 @@ impl Display for DnsType {
             DnsType::A => write!(f, "A"),
             DnsType::AAAA => write!(f, "AAAA"),
             DnsType::CNAME => write!(f, "CNAME"),
+            DnsType::MX => write!(f, "MX"),
+            DnsType::TXT => write!(f, "TXT"),
         }
     }
 }
 
 #[async_trait]
-pub trait DnsProviderTrait {
+pub trait DnsProviderTrait: Send + Sync {
+    /// The name of the zone managed by this provider, e.g. `example.com`. Records passed to the
+    /// provider have FQDNs inside this zone.
+    fn zone_name(&self) -> &str;
+
+    /// Lists the records in the zone with their IDs. Hostnames are FQDNs and records of
+    /// unsupported types are skipped.
+    async fn list_dns_records(&self) -> Result<Vec<(String, consul::DnsRecord)>>;
+
     async fn create_dns_record<'a>(&self, dns_record: &'a consul::DnsRecord) -> Result<String>;
 
+    /// Updates the value and TTL of an existing record in place and returns its possibly changed
+    /// ID.
+    async fn update_dns_record<'a>(
+        &self,
+        record_id: &'a str,
+        dns_record: &'a consul::DnsRecord,
+    ) -> Result<String>;
+
     async fn delete_dns_record<'a>(&self, record_id: &'a str) -> Result<()>;
+
+    /// Applies the changes of a plan and records the successful ones in `state`, keyed by their
+    /// record ID. Providers with batch APIs can override this to submit the whole plan at once,
+    /// by default the changes are made one record at a time.
+    async fn apply_plan(&self, plan: &Plan, state: &mut HashMap<String, consul::DnsRecord>) {
+        plan.apply_each(self, state).await
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -5,9 +5,24 @@ use reqwest::{
 };
 use serde_json::json;
 
-use crate::{config::HetznerCloudConfig, consul, dns_trait::DnsProviderTrait};
+use crate::{
+    config::HetznerCloudConfig,
+    consul,
+    dns_trait::{DnsProviderTrait, DnsType},
+    hostname,
+};
 
 mod api {
+    #[derive(serde::Deserialize)]
+    pub(super) struct ZoneResponse {
+        pub(super) zone: Zone,
+    }
+
+    #[derive(serde::Deserialize)]
+    pub(super) struct Zone {
+        pub(super) name: String,
+    }
+
     #[derive(serde::Deserialize)]
     pub(super) struct RrsetResponse {
         pub(super) rrset: Rrset,
@@ -16,9 +31,38 @@ mod api {
     #[derive(serde::Deserialize)]
     pub(super) struct Rrset {
         pub(super) id: String,
+        #[serde(default)]
+        pub(super) ttl: Option<i32>,
+    }
+
+    /// A page of RRsets of a zone, including RRsets of types that aren't supported
+    #[derive(serde::Deserialize)]
+    pub(super) struct RrsetsPage {
+        pub(super) rrsets: Vec<RawRrset>,
+        #[serde(default)]
+        pub(super) meta: Option<Meta>,
+    }
+
+    #[derive(serde::Deserialize)]
+    pub(super) struct RawRrset {
+        pub(super) id: String,
+        pub(super) name: String,
+        #[serde(rename = "type")]
+        pub(super) type_: String,
+        pub(super) ttl: Option<i32>,
         pub(super) records: Vec<Record>,
     }
 
+    #[derive(serde::Deserialize)]
+    pub(super) struct Meta {
+        pub(super) pagination: Pagination,
+    }
+
+    #[derive(serde::Deserialize)]
+    pub(super) struct Pagination {
+        pub(super) next_page: Option<u32>,
+    }
+
     #[derive(serde::Deserialize)]
     pub(super) struct Record {
         pub(super) value: String,
@@ -38,10 +82,13 @@ mod api {
 pub struct HetznerCloud {
     config: HetznerCloudConfig,
     client: Client,
+    zone_name: String,
 }
 
 impl HetznerCloud {
-    pub fn new(config: HetznerCloudConfig) -> Result<Self> {
+    /// Creates the provider, looking up the name of the configured zone, which may be given by
+    /// its ID or name.
+    pub async fn new(config: HetznerCloudConfig) -> Result<Self> {
         let mut headers = HeaderMap::new();
         let mut auth_value = HeaderValue::from_str(&format!("Bearer {}", config.api_token))
             .context("invalid API token")?;
@@ -49,47 +96,138 @@ impl HetznerCloud {
         headers.insert(AUTHORIZATION, auth_value);
 
         let client = Client::builder().default_headers(headers).build()?;
-        Ok(Self { config, client })
-    }
 
-    async fn check_existing_record_matches(
-        &self,
-        dns_record: &consul::DnsRecord,
-    ) -> Result<String> {
-        let mut url = self.config.api_url.clone();
+        let mut url = config.api_url.clone();
         url.path_segments_mut()
             .map_err(|_| anyhow!("Invalid Hetzner Cloud API url"))?
             .push("zones")
-            .push(&self.config.dns_zone)
-            .push("rrsets")
-            .push(&dns_record.hostname)
-            .push(&dns_record.type_.to_string());
-
-        let body = self
-            .client
+            .push(&config.dns_zone);
+        let zone_name = client
             .get(url)
             .send()
             .await?
             .error_for_status()?
-            .json::<api::RrsetResponse>()
-            .await?;
+            .json::<api::ZoneResponse>()
+            .await?
+            .zone
+            .name;
 
-        let record = body
-            .rrset
-            .records
-            .first()
-            .context("no records in RRset response")?;
+        Ok(Self {
+            config,
+            client,
+            zone_name,
+        })
+    }
 
-        if record.value != dns_record.value {
-            bail!("invalid conflicting DNS record found");
+    /// Returns the URL of an RRset, or of one of its actions
+    fn rrset_url(&self, name: &str, type_: &str, action: Option<&str>) -> Result<reqwest::Url> {
+        let mut url = self.config.api_url.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow!("Invalid Hetzner Cloud API url"))?;
+            segments
+                .push("zones")
+                .push(&self.config.dns_zone)
+                .push("rrsets")
+                .push(name)
+                .push(type_);
+            if let Some(action) = action {
+                segments.push("actions").push(action);
+            }
         }
+        Ok(url)
+    }
 
-        Ok(body.rrset.id)
+    /// Runs an action on an RRset, e.g. `add_records`
+    async fn rrset_action(
+        &self,
+        name: &str,
+        type_: &str,
+        action: &str,
+        body: serde_json::Value,
+    ) -> Result<()> {
+        self.client
+            .post(self.rrset_url(name, type_, Some(action))?)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
     }
 }
 
+/// Returns the ID of a single value of an RRset, `<name>/<type>/<value>`, since all values of an
+/// RRset share the ID `<name>/<type>` of the RRset
+fn value_record_id(rrset_id: &str, value: &str) -> String {
+    format!("{}/{}", rrset_id, value)
+}
+
+/// Splits a record ID into the name and type of its RRset and its value. IDs stored by earlier
+/// versions have no value and denote the whole RRset.
+fn parse_record_id(record_id: &str) -> Result<(&str, &str, Option<&str>)> {
+    let (name, rest) = record_id.split_once('/').context("invalid RRset ID")?;
+    Ok(match rest.split_once('/') {
+        Some((type_, value)) => (name, type_, Some(value)),
+        None => (name, rest, None),
+    })
+}
+
 #[async_trait::async_trait]
 impl DnsProviderTrait for HetznerCloud {
+    fn zone_name(&self) -> &str {
+        &self.zone_name
+    }
+
+    async fn list_dns_records(&self) -> Result<Vec<(String, consul::DnsRecord)>> {
+        let mut records = Vec::new();
+        let mut page = Some(1);
+        while let Some(current_page) = page {
+            let mut url = self.config.api_url.clone();
+            url.path_segments_mut()
+                .map_err(|_| anyhow!("Invalid Hetzner Cloud API url"))?
+                .push("zones")
+                .push(&self.config.dns_zone)
+                .push("rrsets");
+            url.query_pairs_mut()
+                .append_pair("page", &current_page.to_string())
+                .append_pair("per_page", "100");
+
+            let response = self
+                .client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<api::RrsetsPage>()
+                .await?;
+
+            for rrset in response.rrsets {
+                let Ok(type_) = rrset.type_.parse::<DnsType>() else {
+                    continue;
+                };
+                let hostname = hostname::from_provider(&rrset.name, &self.zone_name);
+                records.extend(rrset.records.into_iter().map(|record| {
+                    (
+                        value_record_id(&rrset.id, &record.value),
+                        consul::DnsRecord {
+                            hostname: hostname.clone(),
+                            type_: type_.clone(),
+                            ttl: rrset.ttl,
+                            value: record.value,
+                            service: None,
+                            priority: None,
+                        },
+                    )
+                }));
+            }
+
+            page = response.meta.and_then(|meta| meta.pagination.next_page);
+        }
+
+        Ok(records)
+    }
+
     async fn create_dns_record<'a>(&self, dns_record: &'a consul::DnsRecord) -> Result<String> {
         let mut url = self.config.api_url.clone();
         url.path_segments_mut()
@@ -102,7 +240,8 @@ impl DnsProviderTrait for HetznerCloud {
             .client
             .post(url)
             .json(&json!({
-                "name": dns_record.hostname,
+                // RRset names are relative to the zone
+                "name": hostname::relative(&dns_record.hostname, &self.zone_name),
                 "records": [
                     {
                         "value": dns_record.value,
@@ -123,40 +262,121 @@ impl DnsProviderTrait for HetznerCloud {
             .await?;
 
         // If an RRset already exists for the given name and type combination, we get back a 409
-        // CONFLICT response with an error code of `uniqueness_error`.
+        // CONFLICT response with an error code of `uniqueness_error`. The value is then added to
+        // the existing RRset, e.g. for the `A` records of several instances of a service.
         if res.status() == StatusCode::CONFLICT {
             let body = res.json::<api::ErrorResponse>().await?;
 
-            if body.error.code == "uniqueness_error" {
-                return self.check_existing_record_matches(dns_record).await;
-            } else {
+            if body.error.code != "uniqueness_error" {
                 bail!("Unexpected error code {}", body.error.code)
             }
+            let name = hostname::relative(&dns_record.hostname, &self.zone_name);
+            let type_ = dns_record.type_.to_string();
+            self.rrset_action(
+                &name,
+                &type_,
+                "add_records",
+                json!({ "records": [{ "value": dns_record.value }] }),
+            )
+            .await?;
+            return Ok(value_record_id(
+                &format!("{}/{}", name, type_),
+                &dns_record.value,
+            ));
         }
 
-        let record_id = res
+        let rrset_id = res
             .error_for_status()?
             .json::<api::RrsetResponse>()
             .await?
             .rrset
             .id;
-        Ok(record_id)
+        Ok(value_record_id(&rrset_id, &dns_record.value))
+    }
+
+    async fn update_dns_record<'a>(
+        &self,
+        record_id: &'a str,
+        dns_record: &'a consul::DnsRecord,
+    ) -> Result<String> {
+        let (name, type_, old_value) = parse_record_id(record_id)?;
+
+        // Change the TTL only if it differs, since it's a separate action on the RRset
+        let rrset = self
+            .client
+            .get(self.rrset_url(name, type_, None)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<api::RrsetResponse>()
+            .await?
+            .rrset;
+        if rrset.ttl != dns_record.ttl {
+            self.rrset_action(name, type_, "change_ttl", json!({ "ttl": dns_record.ttl }))
+                .await?;
+        }
+
+        match old_value {
+            Some(old_value) if old_value == dns_record.value => {}
+            // Only the value of this record is replaced, keeping the other values of the RRset.
+            // The new value is added first so that the hostname keeps resolving.
+            Some(old_value) => {
+                self.rrset_action(
+                    name,
+                    type_,
+                    "add_records",
+                    json!({ "records": [{ "value": dns_record.value }] }),
+                )
+                .await?;
+                self.rrset_action(
+                    name,
+                    type_,
+                    "remove_records",
+                    json!({ "records": [{ "value": old_value }] }),
+                )
+                .await?;
+            }
+            // IDs of earlier versions denote the whole RRset
+            None => {
+                self.rrset_action(
+                    name,
+                    type_,
+                    "set_records",
+                    json!({ "records": [{ "value": dns_record.value }] }),
+                )
+                .await?;
+            }
+        }
+
+        Ok(value_record_id(
+            &format!("{}/{}", name, type_),
+            &dns_record.value,
+        ))
     }
 
     async fn delete_dns_record<'a>(&self, record_id: &'a str) -> Result<(), anyhow::Error> {
         // TODO: Consider making the record ID type generic over the DNS provider so we don't have
         // to do this string splitting.
-        let (hostname, type_) = record_id.split_once('/').unwrap();
-        let mut url = self.config.api_url.clone();
-        url.path_segments_mut()
-            .map_err(|_| anyhow!("Invalid Hetzner Cloud API url"))?
-            .push("zones")
-            .push(&self.config.dns_zone)
-            .push("rrsets")
-            .push(hostname)
-            .push(type_);
-
-        self.client.delete(url).send().await?.error_for_status()?;
+        let (name, type_, value) = parse_record_id(record_id)?;
+        match value {
+            // Removing the last value of an RRset deletes the RRset
+            Some(value) => {
+                self.rrset_action(
+                    name,
+                    type_,
+                    "remove_records",
+                    json!({ "records": [{ "value": value }] }),
+                )
+                .await?
+            }
+            None => {
+                self.client
+                    .delete(self.rrset_url(name, type_, None)?)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
         Ok(())
     }
 }
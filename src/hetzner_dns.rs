@@ -8,6 +8,7 @@ use crate::{
     config::HetznerConfig,
     consul,
     dns_trait::{DnsProviderTrait, DnsRecord},
+    hostname,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,13 +21,76 @@ struct AllRecordsResponse {
     records: Vec<DnsRecord>,
 }
 
+/// A page of records of a zone, including records of types that aren't supported
+#[derive(Deserialize)]
+struct RecordsPage {
+    records: Vec<RawRecord>,
+    #[serde(default)]
+    meta: Option<Meta>,
+}
+
+#[derive(Deserialize)]
+struct RawRecord {
+    id: String,
+    #[serde(rename = "type")]
+    type_: String,
+    name: String,
+    value: String,
+    ttl: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    pagination: Pagination,
+}
+
+#[derive(Deserialize)]
+struct Pagination {
+    last_page: u32,
+}
+
+#[derive(Deserialize)]
+struct ZoneResponse {
+    zone: Zone,
+}
+
+#[derive(Deserialize)]
+struct Zone {
+    name: String,
+}
+
 pub struct HetznerDns {
     pub config: HetznerConfig,
     pub reqwest_client: Client,
+    /// The name of the zone with the ID `config.dns_zone_id`
+    pub zone_name: String,
 }
 
 impl HetznerDns {
+    /// Creates the provider, looking up the name of the configured zone.
+    pub async fn new(config: HetznerConfig, reqwest_client: Client) -> Result<Self> {
+        let url = config
+            .api_url
+            .join(&format!("zones/{}", config.dns_zone_id))?;
+        let zone = reqwest_client
+            .get(url)
+            .header("Auth-API-Token", &config.dns_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ZoneResponse>()
+            .await?
+            .zone;
+
+        Ok(Self {
+            config,
+            reqwest_client,
+            zone_name: zone.name,
+        })
+    }
+
     async fn check_record_exists(&self, dns_record: &consul::DnsRecord) -> Option<String> {
+        let name = hostname::relative(&dns_record.hostname, &self.zone_name);
         let mut url = self
             .config
             .api_url
@@ -34,7 +98,7 @@ impl HetznerDns {
             .expect("building URL should never fail");
         url.query_pairs_mut()
             .append_pair("zone_id", &self.config.dns_zone_id)
-            .append_pair("search_name", &dns_record.hostname);
+            .append_pair("search_name", &name);
 
         let res = self
             .reqwest_client
@@ -51,7 +115,7 @@ impl HetznerDns {
 
         for record in res.records {
             if record.type_ == dns_record.type_
-                && record.name == dns_record.hostname
+                && record.name == name
                 && record.value == dns_record.value
             {
                 return Some(record.id);
@@ -64,12 +128,62 @@ impl HetznerDns {
 
 #[async_trait]
 impl DnsProviderTrait for HetznerDns {
+    fn zone_name(&self) -> &str {
+        &self.zone_name
+    }
+
+    async fn list_dns_records(&self) -> Result<Vec<(String, consul::DnsRecord)>> {
+        let mut records = Vec::new();
+        let mut page = 1;
+        loop {
+            let mut url = self.config.api_url.join("records")?;
+            url.query_pairs_mut()
+                .append_pair("zone_id", &self.config.dns_zone_id)
+                .append_pair("page", &page.to_string())
+                .append_pair("per_page", "100");
+
+            let response = self
+                .reqwest_client
+                .get(url)
+                .header("Auth-API-Token", &self.config.dns_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<RecordsPage>()
+                .await?;
+
+            for record in response.records {
+                let Ok(type_) = record.type_.parse() else {
+                    continue;
+                };
+                let hostname = hostname::from_provider(&record.name, &self.zone_name);
+                records.push((
+                    record.id,
+                    consul::DnsRecord {
+                        hostname,
+                        type_,
+                        ttl: record.ttl,
+                        value: record.value,
+                        service: None,
+                        priority: None,
+                    },
+                ));
+            }
+
+            match response.meta {
+                Some(meta) if page < meta.pagination.last_page => page += 1,
+                _ => return Ok(records),
+            }
+        }
+    }
+
     /// Create a DNS record based on the Consul service tags
     async fn create_dns_record<'a>(&self, dns_record: &'a consul::DnsRecord) -> Result<String> {
         let new_record = json!({
             "zone_id": self.config.dns_zone_id,
             "type": dns_record.type_,
-            "name": dns_record.hostname,
+            // The Hetzner DNS API expects names relative to the zone
+            "name": hostname::relative(&dns_record.hostname, &self.zone_name),
             "value": dns_record.value,
             "ttl": dns_record.ttl
         });
@@ -96,6 +210,36 @@ impl DnsProviderTrait for HetznerDns {
         Ok(created_dns.record.id)
     }
 
+    async fn update_dns_record<'a>(
+        &self,
+        record_id: &'a str,
+        dns_record: &'a consul::DnsRecord,
+    ) -> Result<String> {
+        let record = json!({
+            "zone_id": self.config.dns_zone_id,
+            "type": dns_record.type_,
+            "name": hostname::relative(&dns_record.hostname, &self.zone_name),
+            "value": dns_record.value,
+            "ttl": dns_record.ttl
+        });
+
+        let url = self
+            .config
+            .api_url
+            .join(&format!("records/{}", record_id))?;
+        let updated_dns = self
+            .reqwest_client
+            .put(url)
+            .header("Auth-API-Token", &self.config.dns_token)
+            .json(&record)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RecordResponse>()
+            .await?;
+        Ok(updated_dns.record.id)
+    }
+
     async fn delete_dns_record<'a>(&self, record_id: &'a str) -> Result<(), anyhow::Error> {
         let url = self
             .config
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, bail, Result};
+use tracing::{error, info};
+
+use crate::{consul::DnsRecord, dns_trait::DnsType};
+
+/// Normalizes a hostname to a lowercase FQDN without trailing dot inside `zone`.
+///
+/// Hostnames are interpreted like names in a zone file: `@` denotes the apex of the zone, names
+/// ending with a dot are absolute and names ending with the zone are taken as FQDNs, while all
+/// other names are relative to the zone. Absolute names outside the zone are rejected.
+///
+/// Internationalized hostnames are converted to their ASCII form (punycode) following UTS #46,
+/// e.g. `bücher.example.com` becomes `xn--bcher-kva.example.com`.
+pub fn normalize(hostname: &str, zone: &str) -> Result<String> {
+    let zone = to_ascii(zone.trim_end_matches('.'))?;
+    let hostname = hostname.trim();
+
+    if hostname.is_empty() {
+        bail!("Empty hostname");
+    }
+    if hostname == "@" {
+        return Ok(zone);
+    }
+
+    let (name, absolute) = match hostname.strip_suffix('.') {
+        Some(name) => (name, true),
+        None => (hostname, false),
+    };
+    if name.is_empty() || name.split('.').any(str::is_empty) {
+        bail!("Invalid hostname `{}`", hostname);
+    }
+    let name = to_ascii(name)?;
+
+    if is_in_zone(&name, &zone) {
+        Ok(name)
+    } else if absolute {
+        bail!("Hostname `{}` is outside of the zone `{}`", hostname, zone)
+    } else {
+        Ok(format!("{}.{}", name, zone))
+    }
+}
+
+/// Converts a domain name to its lowercase ASCII form
+fn to_ascii(name: &str) -> Result<String> {
+    idna::domain_to_ascii(name)
+        .map_err(|_| anyhow!("Invalid internationalized hostname `{}`", name))
+}
+
+/// Returns the FQDN of a name returned by a DNS provider, which is always relative to `zone` or
+/// `@` for the apex. Unlike [`normalize`] it's not interpreted like user input, so names such as
+/// `_external-dns.web` always end up inside the zone.
+pub fn from_provider(name: &str, zone: &str) -> String {
+    let zone = to_ascii(zone.trim_end_matches('.')).unwrap_or(zone.to_lowercase());
+    let name = name.trim();
+    if name == "@" || name.is_empty() {
+        return zone;
+    }
+    let name = to_ascii(name).unwrap_or(name.to_lowercase());
+    format!("{}.{}", name, zone)
+}
+
+/// Returns the name of an FQDN relative to `zone`, i.e. without the zone suffix, or `@` for the
+/// apex of the zone. Names outside the zone are returned unchanged.
+pub fn relative(fqdn: &str, zone: &str) -> String {
+    let zone = to_ascii(zone.trim_end_matches('.')).unwrap_or(zone.to_string());
+    let zone = zone.as_str();
+    let fqdn = fqdn.trim_end_matches('.');
+
+    if fqdn.eq_ignore_ascii_case(zone) {
+        return "@".to_string();
+    }
+    match fqdn
+        .len()
+        .checked_sub(zone.len() + 1)
+        .filter(|_| is_in_zone(fqdn, zone))
+    {
+        Some(end) => fqdn[..end].to_string(),
+        None => fqdn.to_string(),
+    }
+}
+
+/// Returns whether `name` is the apex of `zone` or one of its subdomains
+fn is_in_zone(name: &str, zone: &str) -> bool {
+    let name = name.to_lowercase();
+    let zone = zone.to_lowercase();
+    name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+/// Normalizes the hostnames of a stored state in place. Records stored by earlier versions may use
+/// hostnames relative to `zone`, while hostnames that can't be normalized are kept as they are.
+pub fn normalize_state(state: &mut HashMap<String, DnsRecord>, zone: &str) {
+    for record in state.values_mut() {
+        if let Ok(hostname) = normalize(&record.hostname, zone) {
+            record.hostname = hostname;
+        }
+    }
+}
+
+/// Normalizes the hostnames of the given records and deduplicates them. Records whose hostname is
+/// invalid or outside of `zone` are skipped.
+///
+/// The target names of `CNAME` and `MX` records are converted to their ASCII form as well, so
+/// they compare equal to the values returned by the DNS provider.
+pub fn normalize_records(records: Vec<DnsRecord>, zone: &str) -> Vec<DnsRecord> {
+    let mut normalized = HashSet::new();
+    for mut record in records {
+        let normalized_record = normalize(&record.hostname, zone)
+            .and_then(|hostname| Ok((hostname, normalize_value(&record)?)));
+        match normalized_record {
+            Ok((hostname, value)) => {
+                if !record.hostname.is_ascii() {
+                    info!(
+                        "Converted internationalized hostname `{}` to `{}`",
+                        record.hostname, hostname
+                    );
+                }
+                if value != record.value {
+                    info!(
+                        "Converted internationalized target `{}` to `{}`",
+                        record.value, value
+                    );
+                }
+                record.hostname = hostname;
+                record.value = value;
+                normalized.insert(record);
+            }
+            Err(e) => error!("Skipping DNS record: {}", e),
+        }
+    }
+
+    normalized.into_iter().collect()
+}
+
+/// Converts the target name in the value of `CNAME` and `MX` records to its ASCII form. Other
+/// values and ASCII targets are returned unchanged.
+fn normalize_value(record: &DnsRecord) -> Result<String> {
+    match record.type_ {
+        DnsType::CNAME => target_to_ascii(&record.value),
+        DnsType::MX => match record.value.trim().split_once(char::is_whitespace) {
+            Some((preference, target)) => Ok(format!(
+                "{} {}",
+                preference,
+                target_to_ascii(target.trim())?
+            )),
+            None => Ok(record.value.clone()),
+        },
+        _ => Ok(record.value.clone()),
+    }
+}
+
+/// Converts an internationalized target name to its ASCII form, keeping a trailing dot
+fn target_to_ascii(target: &str) -> Result<String> {
+    if target.is_ascii() {
+        return Ok(target.to_string());
+    }
+    match target.strip_suffix('.') {
+        Some(name) => Ok(format!("{}.", to_ascii(name)?)),
+        None => to_ascii(target),
+    }
+}
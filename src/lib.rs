@@ -1,5 +1,14 @@
 pub mod config;
+pub mod conflict;
 pub mod consul;
 pub mod dns_trait;
 pub mod hetzner_cloud;
 pub mod hetzner_dns;
+pub mod hostname;
+pub mod nomad;
+pub mod plan;
+pub mod record_source;
+pub mod registry;
+pub mod static_file;
+pub mod template;
+mod traefik;
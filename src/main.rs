@@ -1,21 +1,34 @@
-use std::time::Duration;
+use std::{collections::HashMap, process::ExitCode, time::Duration};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use consul_external_dns::hetzner_cloud::HetznerCloud;
 use reqwest::Client;
-use tokio::time::sleep;
+use tokio::time::{interval_at, sleep, Instant, Interval, MissedTickBehavior};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-use consul_external_dns::config::{Config, DnsProvider};
-use consul_external_dns::consul::ConsulClient;
+use consul_external_dns::config::{Command, Config, DnsProvider};
+use consul_external_dns::conflict;
+use consul_external_dns::consul::{
+    ConsulClient, ConsulGatewaySource, ConsulHttpOptions, ConsulKvSource, ConsulServiceSource,
+    DnsRecord,
+};
 use consul_external_dns::dns_trait::DnsProviderTrait;
 use consul_external_dns::hetzner_dns;
+use consul_external_dns::hostname;
+use consul_external_dns::nomad::NomadSource;
+use consul_external_dns::plan::{self, DeletionThreshold, Plan, Policy};
+use consul_external_dns::record_source::{RecordSource, RecordSources};
+use consul_external_dns::registry::TxtRegistry;
+use consul_external_dns::static_file::StaticFileSource;
+
+/// How often a plan refused for exceeding the deletion threshold is retried
+const MASS_DELETION_RETRY_INTERVAL: Duration = Duration::from_secs(30);
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::try_from_default_env().unwrap_or("info".parse().unwrap()))
         .init();
@@ -38,132 +51,406 @@ async fn main() -> Result<()> {
     let config = Config::try_parse()?;
     info!("Configuration parsed successfully");
 
-    let dns_provider: Box<dyn DnsProviderTrait> = match config.clone().dns_provider {
-        DnsProvider::Hetzner(config) => Box::new(hetzner_dns::HetznerDns {
-            config,
-            reqwest_client: Client::new(),
-        }),
-        DnsProvider::HetznerCloud(config) => Box::new(HetznerCloud::new(config)?),
+    let dns_provider: Box<dyn DnsProviderTrait> = match config.command.dns_provider().clone() {
+        DnsProvider::Hetzner(config) => {
+            Box::new(hetzner_dns::HetznerDns::new(config, Client::new()).await?)
+        }
+        DnsProvider::HetznerCloud(config) => Box::new(HetznerCloud::new(config).await?),
     };
+    info!(
+        "Managing DNS records of zone `{}`",
+        dns_provider.zone_name()
+    );
 
     // Initialize Consul Client
     debug!("Creating Consul client");
-    let consul_client = ConsulClient::new(
-        config.consul_address.clone(),
-        // Can not use datacenter until this PR is merged:
-        // https://github.com/hashicorp/consul/pull/21208
-        None,
-    )?;
+    let consul_token = match &config.consul_token_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read Consul token file {}", path.display()))?
+                .trim()
+                .to_string(),
+        ),
+        None => config.consul_token.clone(),
+    };
+    let http_options = ConsulHttpOptions {
+        token: consul_token,
+        ca_cert: config.consul_ca_cert.clone(),
+        client_cert: config
+            .consul_client_cert
+            .clone()
+            .zip(config.consul_client_key.clone()),
+        tls_server_name: config.consul_tls_server_name.clone(),
+    };
+    let consul_client = ConsulClient {
+        namespace: config.consul_namespace.clone(),
+        partition: config.consul_partition.clone(),
+        ..ConsulClient::with_options(
+            config.consul_address.clone(),
+            // Can not use datacenter until this PR is merged:
+            // https://github.com/hashicorp/consul/pull/21208
+            // The lock and the state are therefore always kept in the datacenter of the Consul
+            // agent, only the record sources are scoped to the configured datacenters.
+            None,
+            config.consul_passing_only,
+            config.traefik_target.clone(),
+            &http_options,
+        )?
+    };
     info!("Created Consul client successfully");
 
-    // Create Consul session
-    debug!("Creating Consul session");
-    let consul_session = consul_client.create_session(cancel_token.clone()).await?;
-    let session_id = consul_session.session_id;
-    info!("Created Consul session successfully");
-
-    // Acquire Lock
-    debug!("Acquiring Consul lock");
-    if cancel_token
-        .run_until_cancelled(consul_client.acquire_lock(session_id))
-        .await
-        .transpose()?
-        .is_none()
-    {
-        return Ok(());
+    // A dry run or plan never writes to Consul, so it runs alongside the active instance without
+    // taking the lock
+    let read_only = config.dry_run || matches!(config.command, Command::Plan { .. });
+    let consul_session = if read_only {
+        info!("Running in read-only mode, no changes will be made");
+        None
+    } else {
+        // Create Consul session
+        debug!("Creating Consul session");
+        let consul_session = consul_client.create_session(cancel_token.clone()).await?;
+        let session_id = consul_session.session_id;
+        info!("Created Consul session successfully");
+
+        // Acquire Lock
+        debug!("Acquiring Consul lock");
+        if cancel_token
+            .run_until_cancelled(consul_client.acquire_lock(session_id))
+            .await
+            .transpose()?
+            .is_none()
+        {
+            return Ok(ExitCode::SUCCESS);
+        };
+        consul_client.acquire_lock(session_id).await?;
+        info!("Acquired Consul lock successfully");
+        Some(consul_session)
     };
-    consul_client.acquire_lock(session_id).await?;
-    info!("Acquired Consul lock successfully");
 
-    process_dns_records(consul_client, dns_provider, cancel_token).await?;
+    let mut record_sources: Vec<Box<dyn RecordSource>> = Vec::new();
+    let datacenters = match config.consul_datacenters.is_empty() {
+        true => vec![None],
+        false => config
+            .consul_datacenters
+            .iter()
+            .cloned()
+            .map(Some)
+            .collect(),
+    };
+    for datacenter in datacenters {
+        debug!(
+            "Creating Consul record sources for datacenter {:?}",
+            datacenter
+        );
+        let client = ConsulClient {
+            datacenter,
+            ..consul_client.clone()
+        };
+        record_sources.push(Box::new(ConsulServiceSource::new(client.clone())));
+        if config.consul_gateways {
+            record_sources.push(Box::new(ConsulGatewaySource::new(client)));
+        }
+    }
+    if let Some(prefix) = config.consul_kv_prefix.clone() {
+        debug!("Creating Consul KV record source");
+        record_sources.push(Box::new(ConsulKvSource::new(consul_client.clone(), prefix)));
+    }
+    if let Some(nomad_address) = config.nomad_address.clone() {
+        debug!("Creating Nomad record source");
+        record_sources.push(Box::new(NomadSource::new(
+            nomad_address,
+            config.nomad_token.clone(),
+            config.traefik_target.clone(),
+        )?));
+    }
+
+    if let Some(records_file) = config.records_file.clone() {
+        debug!("Creating static file record source");
+        record_sources.push(Box::new(StaticFileSource::new(
+            records_file,
+            config.records_file_poll_interval,
+        )));
+    }
 
-    consul_session.join_handle.await?;
+    let plan_options = PlanOptions {
+        policy: config.policy,
+        max_deletions: config.max_deletions,
+        allow_mass_deletion: config.allow_mass_deletion,
+        dry_run: read_only,
+        registry: config
+            .txt_owner_id
+            .clone()
+            .map(|owner_id| TxtRegistry::new(owner_id, config.txt_prefix.clone())),
+    };
+    let exit_code = match config.command {
+        Command::Run(_) => {
+            process_dns_records(
+                &consul_client,
+                dns_provider.as_ref(),
+                record_sources,
+                &plan_options,
+                config.resync_interval,
+                cancel_token,
+            )
+            .await?;
+            ExitCode::SUCCESS
+        }
+        Command::Plan { .. } | Command::Apply { .. } => {
+            let exit_code = process_dns_records_once(
+                &consul_client,
+                dns_provider.as_ref(),
+                record_sources,
+                &plan_options,
+            )
+            .await;
+            // Release the lock
+            cancel_token.cancel();
+            exit_code?
+        }
+    };
 
-    Ok(())
+    if let Some(consul_session) = consul_session {
+        consul_session.join_handle.await?;
+    }
+
+    Ok(exit_code)
+}
+
+/// Fetches the DNS records stored in the Consul state, keyed by their ID in the DNS provider
+async fn fetch_state(
+    consul_client: &ConsulClient,
+    dns_provider: &dyn DnsProviderTrait,
+) -> Result<HashMap<String, DnsRecord>> {
+    debug!("Fetching DNS records from Consul store");
+    let mut state = consul_client.fetch_all_dns_records().await?;
+    hostname::normalize_state(&mut state, dns_provider.zone_name());
+    info!("Fetched {} DNS records from Consul store", state.len());
+    Ok(state)
+}
+
+/// Compares the records of the record sources with the Consul state and the records in the DNS
+/// provider once, and applies the resulting plan unless it's a dry run.
+///
+/// Returns exit code 2 if the DNS provider deviated from the record sources.
+async fn process_dns_records_once(
+    consul_client: &ConsulClient,
+    dns_provider: &dyn DnsProviderTrait,
+    record_sources: Vec<Box<dyn RecordSource>>,
+    options: &PlanOptions,
+) -> Result<ExitCode> {
+    let state = fetch_state(consul_client, dns_provider).await?;
+
+    debug!("Fetching DNS records from record sources");
+    let desired = RecordSources::spawn(record_sources).changed().await?;
+    let desired = hostname::normalize_records(desired, dns_provider.zone_name());
+    info!("Fetched {} DNS records from record sources", desired.len());
+
+    let live_records = fetch_live_records(dns_provider).await?;
+    let current = reconcile_state(&state, &live_records);
+
+    let (plan, current) = plan_changes(&desired, current, Some(&live_records), options);
+    print_plan(&plan)?;
+    let drift = !plan.is_empty() || current != state;
+
+    if !options.dry_run {
+        if !deletions_allowed(consul_client, &plan, current.len(), options).await? {
+            bail!("Refused to apply the plan");
+        }
+
+        let mut updated = current.clone();
+        plan.apply(dns_provider, &mut updated).await;
+        if updated != state {
+            consul_client
+                .update_consul_dns_records(updated.clone())
+                .await?;
+        }
+        let live_records = fetch_live_records(dns_provider).await?;
+        if !plan_changes(&desired, updated, Some(&live_records), options)
+            .0
+            .is_empty()
+        {
+            bail!("Failed to apply all changes");
+        }
+    }
+
+    Ok(match drift {
+        true => ExitCode::from(2),
+        false => ExitCode::SUCCESS,
+    })
+}
+
+/// Fetches the records in the zone of the DNS provider
+async fn fetch_live_records(
+    dns_provider: &dyn DnsProviderTrait,
+) -> Result<Vec<(String, DnsRecord)>> {
+    debug!("Fetching DNS records from the DNS provider");
+    let live_records = dns_provider.list_dns_records().await?;
+    info!(
+        "Fetched {} DNS records from the DNS provider",
+        live_records.len()
+    );
+    Ok(live_records)
+}
+
+/// Returns the records of the `state` as they currently exist in the DNS provider, warning about
+/// records that were changed or removed outside of this tool.
+fn reconcile_state(
+    state: &HashMap<String, DnsRecord>,
+    live_records: &[(String, DnsRecord)],
+) -> HashMap<String, DnsRecord> {
+    let current = plan::live_state(state, live_records);
+    for (record_id, record) in state {
+        match current.get(record_id) {
+            None => warn!(
+                "DNS record `{}` is missing in the DNS provider",
+                record.hostname
+            ),
+            Some(live_record) if live_record != record => {
+                warn!(
+                    "DNS record `{}` was changed in the DNS provider",
+                    record.hostname
+                )
+            }
+            Some(_) => {}
+        }
+    }
+    current
+}
+
+/// Computes the plan turning the `current` records into the `desired` records, resolving
+/// conflicting records first. With the ownership registry, the records claimed in the `live`
+/// records are adopted first, and the adopted records are returned along with the plan.
+fn plan_changes(
+    desired: &[DnsRecord],
+    current: HashMap<String, DnsRecord>,
+    live_records: Option<&[(String, DnsRecord)]>,
+    options: &PlanOptions,
+) -> (Plan, HashMap<String, DnsRecord>) {
+    let desired = conflict::resolve(desired.to_vec(), &current);
+    let (Some(registry), Some(live_records)) = (&options.registry, live_records) else {
+        let plan = Plan::new(&desired, &current).restrict(options.policy);
+        return (plan, current);
+    };
+
+    let desired = registry.with_companions(&desired);
+    let current = registry.adopt(current, &desired, live_records);
+    let plan = Plan::new(&desired, &current).restrict(options.policy);
+    (registry.restrict(plan, live_records), current)
+}
+
+/// Waits for the next resync, or forever if resyncing is disabled
+async fn next_resync(resync: &mut Option<Interval>) {
+    match resync {
+        Some(resync) => {
+            resync.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The reason the run loop computes a new plan
+enum Wake {
+    /// The record sources returned new records
+    Changed(Vec<DnsRecord>),
+    /// A refused plan is retried
+    Retry,
+    /// The DNS provider is compared with the state
+    Resync,
+}
+
+/// Options controlling which changes of a plan are made
+struct PlanOptions {
+    policy: Policy,
+    max_deletions: Option<DeletionThreshold>,
+    allow_mass_deletion: bool,
+    /// Only print the changes instead of making them
+    dry_run: bool,
+    registry: Option<TxtRegistry>,
 }
 
 async fn process_dns_records(
-    consul_client: ConsulClient,
-    dns_provider: Box<dyn DnsProviderTrait>,
+    consul_client: &ConsulClient,
+    dns_provider: &dyn DnsProviderTrait,
+    record_sources: Vec<Box<dyn RecordSource>>,
+    options: &PlanOptions,
+    resync_interval: Duration,
     cancel_token: CancellationToken,
 ) -> Result<()> {
-    let mut consul_dns_index: Option<String> = None;
+    let mut record_sources = RecordSources::spawn(record_sources);
+    let mut new_dns_tags_from_services = None;
+    let mut refused = false;
+    let mut resync = (!resync_interval.is_zero()).then(|| {
+        let mut resync = interval_at(Instant::now() + resync_interval, resync_interval);
+        resync.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        resync
+    });
 
     loop {
-        // Fetch current DNS records from Consul store
-        debug!("Fetching DNS records from Consul store");
-        let current_consul_dns_records = consul_client.fetch_all_dns_records().await?;
-        info!(
-            "Fetched {} DNS records from Consul store",
-            current_consul_dns_records.len()
-        );
-
-        let mut updated_dns_records = current_consul_dns_records.clone();
-
-        // Fetch DNS tags from the services in Consul
-        // This is the long polling request that will block until there are changes
-        // in the Consul Services. The timeout is set to 100 seconds.
-        debug!("Fetching DNS tags from Consul Services");
-        let Some(new_dns_tags_from_services) = cancel_token
-            .run_until_cancelled(consul_client.fetch_service_tags(&mut consul_dns_index))
-            .await
-            .transpose()?
-        else {
+        // Fetch DNS records from the record sources
+        // This will block until there are changes in any of the record sources, e.g. in the
+        // Consul Services. While a plan is refused for exceeding the deletion threshold, it's
+        // retried periodically in case the deletions were allowed in the meantime. Once the
+        // records are known, the DNS provider is also resynced with the state periodically.
+        debug!("Fetching DNS records from record sources");
+        let synced = new_dns_tags_from_services.is_some();
+        let wake = async {
+            tokio::select! {
+                records = record_sources.changed() => records.map(Wake::Changed),
+                _ = sleep(MASS_DELETION_RETRY_INTERVAL), if refused => Ok(Wake::Retry),
+                _ = next_resync(&mut resync), if synced => Ok(Wake::Resync),
+            }
+        };
+        let Some(wake) = cancel_token.run_until_cancelled(wake).await.transpose()? else {
             return Ok(());
         };
-        info!(
-            "Fetched {} DNS tags from Consul services",
-            new_dns_tags_from_services.len()
-        );
-
-        info!("Services in Consul have changed; updating DNS records in DNS provider.");
+        let resyncing = matches!(wake, Wake::Resync);
+        if let Wake::Changed(records) = wake {
+            let records = hostname::normalize_records(records, dns_provider.zone_name());
+            info!("Fetched {} DNS records from record sources", records.len());
+            info!("Record sources have changed; updating DNS records in DNS provider.");
+            new_dns_tags_from_services = Some(records);
+        }
+        let desired = new_dns_tags_from_services.as_deref().unwrap_or_default();
 
-        debug!("Creating DNS records in the DNS provider");
-        for fetched_dns_record in &new_dns_tags_from_services {
-            if !current_consul_dns_records
-                .values()
-                .any(|r| r == fetched_dns_record)
-            {
-                // If the record is not in the DNS state, create it and store it in Consul
-                match dns_provider.create_dns_record(fetched_dns_record).await {
-                    Ok(record_id) => {
-                        updated_dns_records.insert(record_id, fetched_dns_record.clone());
-                        info!(
-                            "Created DNS record `{}` in DNS provider",
-                            fetched_dns_record.hostname
-                        );
-                    }
-                    Err(e) => {
-                        error!(
-                            "Failed to create DNS record `{}`: {}",
-                            fetched_dns_record.hostname, e
-                        );
-                    }
+        // Fetch current DNS records from Consul store
+        let current_consul_dns_records = fetch_state(consul_client, dns_provider).await?;
+        // The ownership registry needs the records in the DNS provider for every plan. A dry run
+        // never changes them, so like the `plan` subcommand it compares the state with them on
+        // every pass to show what would actually be changed.
+        let reconciling = resyncing || options.dry_run;
+        let live_records = if reconciling || options.registry.is_some() {
+            Some(fetch_live_records(dns_provider).await?)
+        } else {
+            None
+        };
+        let current_dns_records = match &live_records {
+            Some(live_records) if reconciling => {
+                if resyncing {
+                    info!("Resyncing DNS records in DNS provider with the state in Consul");
                 }
+                reconcile_state(&current_consul_dns_records, live_records)
             }
-        }
+            _ => current_consul_dns_records.clone(),
+        };
 
-        // Delete DNS records from Consul state that are not in the fetched_dns_records
-        debug!("Deleting DNS records from the DNS provider");
-        for (record_id, record) in current_consul_dns_records.iter() {
-            if !new_dns_tags_from_services
-                .iter()
-                .any(|fetched_record| fetched_record == record)
-            {
-                // Delete the record from the DNS provider
-                if let Err(e) = dns_provider.delete_dns_record(record_id).await {
-                    error!("Failed to delete DNS record `{}`: {}", record.hostname, e);
-                    continue;
-                };
-                info!("Deleted DNS record `{}` from DNS provider", record.hostname);
-
-                // Remove the record from the new_dns_state hashmap
-                updated_dns_records.remove(record_id);
-            }
+        let (plan, current_dns_records) = plan_changes(
+            desired,
+            current_dns_records,
+            live_records.as_deref(),
+            options,
+        );
+        let mut updated_dns_records = current_dns_records.clone();
+
+        refused =
+            !deletions_allowed(consul_client, &plan, current_dns_records.len(), options).await?;
+        if options.dry_run || refused {
+            print_plan(&plan)?;
+        } else {
+            plan.apply(dns_provider, &mut updated_dns_records).await;
         }
 
         debug!("Storing all DNS records in Consul KV store");
-        if current_consul_dns_records != updated_dns_records {
+        if !options.dry_run && current_consul_dns_records != updated_dns_records {
             match consul_client
                 .update_consul_dns_records(updated_dns_records.clone())
                 .await
@@ -186,3 +473,55 @@ async fn process_dns_records(
         };
     }
 }
+
+/// Returns whether the deletions of a plan stay within `--max-deletions` out of `managed`
+/// records, or were explicitly allowed.
+async fn deletions_allowed(
+    consul_client: &ConsulClient,
+    plan: &Plan,
+    managed: usize,
+    options: &PlanOptions,
+) -> Result<bool> {
+    let Some(threshold) = options.max_deletions else {
+        return Ok(true);
+    };
+    if !plan.exceeds(threshold, managed) {
+        return Ok(true);
+    }
+
+    let deletes = plan.deletes.len();
+    if options.allow_mass_deletion {
+        warn!(
+            "Deleting {} of {} records exceeds the threshold of {}, but mass deletions are allowed",
+            deletes, managed, threshold
+        );
+        return Ok(true);
+    }
+    if !options.dry_run && consul_client.mass_deletion_allowed().await? {
+        warn!(
+            "Deleting {} of {} records exceeds the threshold of {}, but was allowed in Consul",
+            deletes, managed, threshold
+        );
+        consul_client.clear_mass_deletion_override().await?;
+        return Ok(true);
+    }
+
+    error!(
+        "Refusing to delete {} of {} records, exceeding the threshold of {}. Set \
+         `consul_external_dns/allow-mass-deletion` to `true` in Consul to allow it.",
+        deletes, managed, threshold
+    );
+    Ok(false)
+}
+
+/// Logs the changes of a plan and prints each of them as a JSON object on its own line
+fn print_plan(plan: &Plan) -> Result<()> {
+    if plan.is_empty() {
+        info!("No changes planned");
+    }
+    for change in plan.changes() {
+        info!("Planned change: {}", change);
+        println!("{}", serde_json::to_string(&change)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Client, Url,
+};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::{
+    consul::{self, DnsRecord},
+    record_source::RecordSource,
+    template, traefik,
+};
+
+#[derive(Debug, Deserialize)]
+struct ServiceNamespace {
+    #[serde(rename = "Namespace")]
+    namespace: String,
+    #[serde(rename = "Services")]
+    services: Vec<ServiceStub>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceStub {
+    #[serde(rename = "ServiceName")]
+    service_name: String,
+    #[serde(rename = "Tags", default)]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceRegistration {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(rename = "Datacenter", default)]
+    datacenter: String,
+    #[serde(rename = "Tags", default)]
+    tags: Vec<String>,
+}
+
+/// A record source reading the tags of services registered with Nomad's native service
+/// discovery, i.e. services using `provider = "nomad"`.
+pub struct NomadSource {
+    http_client: Client,
+    api_base_url: Url,
+    traefik_target: Option<String>,
+    nomad_index: Option<String>,
+}
+
+impl NomadSource {
+    pub fn new(
+        nomad_address: Url,
+        nomad_token: Option<String>,
+        traefik_target: Option<String>,
+    ) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = nomad_token {
+            let mut token = HeaderValue::from_str(&token)?;
+            token.set_sensitive(true);
+            headers.insert("X-Nomad-Token", token);
+        }
+
+        Ok(Self {
+            http_client: Client::builder().default_headers(headers).build()?,
+            api_base_url: nomad_address.join("v1/")?,
+            traefik_target,
+            nomad_index: None,
+        })
+    }
+
+    /// Retrieves the services of all namespaces. If `nomad_index` is set, this is a blocking query
+    /// that only returns once a service registration has changed since that index.
+    async fn fetch_services(&mut self) -> Result<Vec<ServiceNamespace>> {
+        let mut req = self
+            .http_client
+            .get(self.api_base_url.join("services")?)
+            .query(&[("namespace", "*")]);
+
+        if let Some(index) = &self.nomad_index {
+            req = req.query(&[("index", index)]);
+        }
+
+        let response = req.send().await?.error_for_status()?;
+
+        if let Some(index_header) = response.headers().get("X-Nomad-Index") {
+            if let Ok(index_str) = index_header.to_str() {
+                let _ = self.nomad_index.insert(index_str.to_string());
+            } else {
+                error!("Failed to convert X-Nomad-Index header to string");
+            }
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Retrieves the registrations of all allocations of a service
+    async fn fetch_service_registrations(
+        &self,
+        namespace: &str,
+        service_name: &str,
+    ) -> Result<Vec<ServiceRegistration>> {
+        let mut url = self.api_base_url.join("service/")?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid Nomad API url"))?
+            .pop_if_empty()
+            .push(service_name);
+
+        Ok(self
+            .http_client
+            .get(url)
+            .query(&[("namespace", namespace)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Returns whether any registration of a service may declare DNS records
+    fn is_enabled(&self, tags: &[String]) -> bool {
+        tags.iter().any(|tag| {
+            tag == consul::TAG_ENABLE
+                || (self.traefik_target.is_some() && tag == traefik::TAG_ENABLE)
+        })
+    }
+}
+
+#[async_trait]
+impl RecordSource for NomadSource {
+    async fn fetch_records(&mut self) -> Result<Vec<DnsRecord>> {
+        let namespaces = self.fetch_services().await?;
+
+        let mut records = HashSet::new();
+        for namespace in namespaces {
+            for service in namespace.services {
+                if !self.is_enabled(&service.tags) {
+                    continue;
+                }
+
+                // Tags are parsed per allocation, so every allocation contributes its own records
+                let mut registrations = self
+                    .fetch_service_registrations(&namespace.namespace, &service.service_name)
+                    .await?;
+                // Order the allocations so that `{{index}}` is stable across queries
+                registrations.sort_by(|a, b| a.id.cmp(&b.id));
+                for (index, registration) in registrations.into_iter().enumerate() {
+                    if registration
+                        .tags
+                        .iter()
+                        .any(|tag| tag == consul::TAG_ENABLE)
+                    {
+                        // Hostnames may be templates referencing attributes of the allocation
+                        let variables = HashMap::from([
+                            ("service".to_string(), service.service_name.clone()),
+                            ("dc".to_string(), registration.datacenter.clone()),
+                            ("index".to_string(), index.to_string()),
+                        ]);
+                        records.extend(
+                            template::render_hostnames(
+                                consul::parse_dns_tags(registration.tags.clone()),
+                                &variables,
+                            )
+                            .into_iter()
+                            .map(|record| owned_by(record, &service.service_name)),
+                        );
+                    }
+                    if let Some(target) = &self.traefik_target {
+                        if registration
+                            .tags
+                            .iter()
+                            .any(|tag| tag == traefik::TAG_ENABLE)
+                        {
+                            records.extend(
+                                traefik::parse_traefik_tags(&registration.tags, target)
+                                    .into_iter()
+                                    .map(|record| owned_by(record, &service.service_name)),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(records.into_iter().collect())
+    }
+}
+
+/// Sets the service owning a record
+fn owned_by(record: DnsRecord, service_name: &str) -> DnsRecord {
+    DnsRecord {
+        service: Some(service_name.to_string()),
+        ..record
+    }
+}
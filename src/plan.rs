@@ -0,0 +1,353 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use serde::Serialize;
+
+use tracing::{debug, error, info, warn};
+
+use crate::{consul::DnsRecord, dns_trait::DnsProviderTrait};
+
+/// Restricts which kinds of changes are made to the records in the DNS provider
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Policy {
+    /// Create, update and delete records
+    #[default]
+    Sync,
+    /// Create and update records, but never delete them
+    UpsertOnly,
+    /// Only create records, but never update or delete them
+    CreateOnly,
+}
+
+/// Limit on the number of records a single plan may delete, either as an absolute number, e.g.
+/// `10`, or as a percentage of the managed records, e.g. `25%`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeletionThreshold {
+    Count(usize),
+    Percentage(u8),
+}
+
+impl std::str::FromStr for DeletionThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_suffix('%') {
+            Some(percentage) => match percentage.trim().parse() {
+                Ok(percentage) if percentage <= 100 => {
+                    Ok(DeletionThreshold::Percentage(percentage))
+                }
+                _ => Err(format!("invalid percentage `{}`", s)),
+            },
+            None => s
+                .trim()
+                .parse()
+                .map(DeletionThreshold::Count)
+                .map_err(|_| format!("invalid number of records `{}`", s)),
+        }
+    }
+}
+
+impl Display for DeletionThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeletionThreshold::Count(count) => write!(f, "{} records", count),
+            DeletionThreshold::Percentage(percentage) => {
+                write!(f, "{}% of the managed records", percentage)
+            }
+        }
+    }
+}
+
+/// The changes needed to bring the records in the DNS provider in line with the desired records
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Records that don't exist yet
+    pub creates: Vec<DnsRecord>,
+    /// Existing records whose value or TTL changed
+    pub updates: Vec<Update>,
+    /// Existing records that are no longer desired
+    pub deletes: Vec<Delete>,
+}
+
+/// An existing record that is updated in place
+#[derive(Clone, Debug, PartialEq)]
+pub struct Update {
+    pub record_id: String,
+    pub old: DnsRecord,
+    pub new: DnsRecord,
+}
+
+/// An existing record that is deleted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delete {
+    pub record_id: String,
+    pub record: DnsRecord,
+    /// Whether the hostname of the record is still published, but only with values created by
+    /// the plan. The record is then only deleted once one of them was created, so the hostname
+    /// never ends up without any record.
+    pub replaced: bool,
+}
+
+/// A single change of a plan. It's serialized to a JSON object with an `action` field for
+/// machine-readable output.
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Change<'a> {
+    Create {
+        record: &'a DnsRecord,
+    },
+    Update {
+        record_id: &'a str,
+        old: &'a DnsRecord,
+        new: &'a DnsRecord,
+    },
+    Delete {
+        record_id: &'a str,
+        record: &'a DnsRecord,
+    },
+}
+
+impl Display for Change<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Create { record } => write!(f, "create {}", DisplayRecord(record)),
+            Change::Update { old, new, .. } => {
+                write!(f, "update {} to {}", DisplayRecord(old), DisplayRecord(new))
+            }
+            Change::Delete { record, .. } => write!(f, "delete {}", DisplayRecord(record)),
+        }
+    }
+}
+
+/// Formats a record like a line of a zone file, e.g. `web.example.com 300 A 10.0.0.1`
+struct DisplayRecord<'a>(&'a DnsRecord);
+
+impl Display for DisplayRecord<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let record = self.0;
+        write!(f, "`{}", record.hostname)?;
+        if let Some(ttl) = record.ttl {
+            write!(f, " {}", ttl)?;
+        }
+        write!(f, " {} {}`", record.type_, record.value)
+    }
+}
+
+impl Plan {
+    /// Computes the changes turning the `current` records, keyed by their ID in the DNS provider,
+    /// into the `desired` records.
+    ///
+    /// Records with the same hostname, type and service as a desired record, but a different
+    /// value or TTL are updated in place instead of being deleted and recreated.
+    pub fn new(desired: &[DnsRecord], current: &HashMap<String, DnsRecord>) -> Plan {
+        // Iterate the current records in a stable order so that the plan is deterministic
+        let mut current: Vec<(&String, &DnsRecord)> = current.iter().collect();
+        current.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut plan = Plan::default();
+        let mut updated_record_ids = HashSet::new();
+        for record in desired {
+            if current.iter().any(|(_, r)| *r == record) {
+                continue;
+            }
+
+            let outdated_record = current.iter().find(|(record_id, r)| {
+                r.same_identity(record)
+                    && !updated_record_ids.contains(*record_id)
+                    && !desired.contains(r)
+            });
+            match outdated_record {
+                Some((record_id, old)) => {
+                    updated_record_ids.insert(*record_id);
+                    plan.updates.push(Update {
+                        record_id: record_id.to_string(),
+                        old: (*old).clone(),
+                        new: record.clone(),
+                    });
+                }
+                None => plan.creates.push(record.clone()),
+            }
+        }
+
+        for &(record_id, record) in &current {
+            if updated_record_ids.contains(record_id) || desired.contains(record) {
+                continue;
+            }
+
+            // Hostnames with unchanged or updated records keep resolving in any case
+            let same_name =
+                |r: &DnsRecord| r.hostname == record.hostname && r.type_ == record.type_;
+            let replaced = desired.iter().any(same_name)
+                && !current.iter().any(|(id, r)| {
+                    same_name(r) && (desired.contains(r) || updated_record_ids.contains(id))
+                });
+            plan.deletes.push(Delete {
+                record_id: record_id.clone(),
+                record: record.clone(),
+                replaced,
+            });
+        }
+
+        plan
+    }
+
+    /// Drops the changes that aren't allowed by the policy
+    pub fn restrict(mut self, policy: Policy) -> Plan {
+        if policy != Policy::Sync {
+            self.deletes.clear();
+        }
+        if policy == Policy::CreateOnly {
+            self.updates.clear();
+        }
+        self
+    }
+
+    /// Returns whether the plan deletes more records than allowed by the threshold, out of
+    /// `managed` records owned by this tool.
+    pub fn exceeds(&self, threshold: DeletionThreshold, managed: usize) -> bool {
+        let deletes = self.deletes.len();
+        match threshold {
+            DeletionThreshold::Count(count) => deletes > count,
+            DeletionThreshold::Percentage(percentage) => {
+                deletes * 100 > percentage as usize * managed
+            }
+        }
+    }
+
+    /// Returns all changes of the plan in the order they are applied
+    pub fn changes(&self) -> Vec<Change<'_>> {
+        let creates = self.creates.iter().map(|record| Change::Create { record });
+        let updates = self.updates.iter().map(|update| Change::Update {
+            record_id: &update.record_id,
+            old: &update.old,
+            new: &update.new,
+        });
+        let deletes = self.deletes.iter().map(|delete| Change::Delete {
+            record_id: &delete.record_id,
+            record: &delete.record,
+        });
+        creates.chain(updates).chain(deletes).collect()
+    }
+
+    /// Returns whether the plan doesn't change anything
+    pub fn is_empty(&self) -> bool {
+        self.creates.is_empty() && self.updates.is_empty() && self.deletes.is_empty()
+    }
+
+    /// Executes the plan against the DNS provider and applies the successful changes to `state`.
+    ///
+    /// The whole plan is handed to [`DnsProviderTrait::apply_plan`], so providers with batch APIs
+    /// can submit it at once.
+    pub async fn apply(
+        &self,
+        dns_provider: &dyn DnsProviderTrait,
+        state: &mut HashMap<String, DnsRecord>,
+    ) {
+        dns_provider.apply_plan(self, state).await
+    }
+
+    /// Executes the plan one record at a time and applies the successful changes to `state`.
+    ///
+    /// Records are created and updated before any record is deleted. Failed changes are logged
+    /// and skipped, so they are retried with the next plan.
+    pub async fn apply_each<P: DnsProviderTrait + ?Sized>(
+        &self,
+        dns_provider: &P,
+        state: &mut HashMap<String, DnsRecord>,
+    ) {
+        debug!("Creating DNS records in the DNS provider");
+        for record in &self.creates {
+            match dns_provider.create_dns_record(record).await {
+                Ok(record_id) => {
+                    state.insert(record_id, record.clone());
+                    info!("Created DNS record `{}` in DNS provider", record.hostname);
+                }
+                Err(e) => {
+                    error!("Failed to create DNS record `{}`: {}", record.hostname, e);
+                }
+            }
+        }
+
+        debug!("Updating DNS records in the DNS provider");
+        for update in &self.updates {
+            match dns_provider
+                .update_dns_record(&update.record_id, &update.new)
+                .await
+            {
+                Ok(record_id) => {
+                    state.remove(&update.record_id);
+                    state.insert(record_id, update.new.clone());
+                    info!(
+                        "Updated DNS record `{}` in DNS provider",
+                        update.new.hostname
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to update DNS record `{}`: {}",
+                        update.new.hostname, e
+                    );
+                }
+            }
+        }
+
+        debug!("Deleting DNS records from the DNS provider");
+        for delete in &self.deletes {
+            let record = &delete.record;
+            if delete.replaced
+                && !self.creates.iter().any(|created| {
+                    created.hostname == record.hostname
+                        && created.type_ == record.type_
+                        && state.values().any(|r| r == created)
+                })
+            {
+                warn!(
+                    "Keeping DNS record `{}` until one of its new values was created",
+                    record.hostname
+                );
+                continue;
+            }
+
+            if let Err(e) = dns_provider.delete_dns_record(&delete.record_id).await {
+                error!("Failed to delete DNS record `{}`: {}", record.hostname, e);
+                continue;
+            };
+            info!("Deleted DNS record `{}` from DNS provider", record.hostname);
+            state.remove(&delete.record_id);
+        }
+    }
+}
+
+/// Returns the records of the `state` as they currently exist in the DNS provider, given the
+/// `live` records of the zone. Records that were removed from the zone are left out, and records
+/// whose value or TTL was changed take on the live value.
+pub fn live_state(
+    state: &HashMap<String, DnsRecord>,
+    live: &[(String, DnsRecord)],
+) -> HashMap<String, DnsRecord> {
+    state
+        .iter()
+        .filter_map(|(record_id, record)| {
+            let mut live_records = live
+                .iter()
+                .filter(|(live_id, _)| live_id == record_id)
+                .map(|(_, live_record)| live_record);
+            if live_records
+                .clone()
+                .any(|live_record| live_record == record)
+            {
+                return Some((record_id.clone(), record.clone()));
+            }
+            let live_record = live_records.next()?;
+            Some((
+                record_id.clone(),
+                DnsRecord {
+                    service: record.service.clone(),
+                    ..live_record.clone()
+                },
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,106 @@
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::{sync::mpsc, time::sleep};
+use tracing::error;
+
+use crate::consul::DnsRecord;
+
+/// Delay before a failed source is fetched again, doubled after every further failure
+const RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper limit of the delay before a failed source is fetched again
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A source of the DNS records that should be published
+#[async_trait]
+pub trait RecordSource: Send {
+    /// Returns all DNS records currently declared in this source.
+    ///
+    /// The first call returns immediately, while every following call blocks until the records
+    /// may have changed since the previous call.
+    async fn fetch_records(&mut self) -> Result<Vec<DnsRecord>>;
+}
+
+/// Watches several record sources at once and merges their records.
+pub struct RecordSources {
+    updates: mpsc::Receiver<(usize, Result<Vec<DnsRecord>>)>,
+    records: Vec<Option<Vec<DnsRecord>>>,
+}
+
+impl RecordSources {
+    /// Spawns a task for each source that keeps fetching its records until the returned value is
+    /// dropped.
+    ///
+    /// An error of the first fetch of a source is returned by [`RecordSources::changed`], while
+    /// later errors are logged and the fetch is retried with an exponential backoff.
+    pub fn spawn(sources: Vec<Box<dyn RecordSource>>) -> Self {
+        let (sender, updates) = mpsc::channel(sources.len().max(1));
+        let records = vec![None; sources.len()];
+
+        for (index, mut source) in sources.into_iter().enumerate() {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let mut reported = false;
+                let mut backoff = RETRY_INITIAL_BACKOFF;
+                loop {
+                    let result = match source.fetch_records().await {
+                        // A source failing on startup is most likely misconfigured
+                        Err(e) if !reported => Err(e),
+                        // Later on the source keeps its last records while it's retried, so a
+                        // transient error neither removes its records nor stops the other sources
+                        Err(e) => {
+                            error!(
+                                "Failed to fetch DNS records, retrying in {:?}: {}",
+                                backoff, e
+                            );
+                            sleep(backoff).await;
+                            backoff = (backoff * 2).min(RETRY_MAX_BACKOFF);
+                            continue;
+                        }
+                        Ok(records) => {
+                            reported = true;
+                            backoff = RETRY_INITIAL_BACKOFF;
+                            Ok(records)
+                        }
+                    };
+                    let failed = result.is_err();
+                    if sender.send((index, result)).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Self { updates, records }
+    }
+
+    /// Waits until any source has changed and returns the deduplicated records of all sources.
+    ///
+    /// Only returns once every source has reported its records at least once, so a slow source
+    /// doesn't lead to its records being considered removed.
+    pub async fn changed(&mut self) -> Result<Vec<DnsRecord>> {
+        loop {
+            let (index, result) = self
+                .updates
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("All record sources have stopped"))?;
+            self.records[index] = Some(result?);
+
+            // Take all other pending updates into account as well, so bursts of changes only
+            // lead to a single update.
+            while let Ok((index, result)) = self.updates.try_recv() {
+                self.records[index] = Some(result?);
+            }
+
+            if self.records.iter().all(Option::is_some) {
+                break;
+            }
+        }
+
+        let records: HashSet<DnsRecord> =
+            self.records.iter().flatten().flatten().cloned().collect();
+        Ok(records.into_iter().collect())
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::{BTreeSet, HashMap};
+
+use tracing::{info, warn};
+
+use crate::{consul::DnsRecord, dns_trait::DnsType, plan::Plan};
+
+/// Marks a TXT record as a companion record written by this tool
+const HERITAGE: &str = "heritage=consul-external-dns";
+/// Key of the owner ID inside a companion record
+const OWNER_KEY: &str = "consul-external-dns/owner";
+/// Label replacing the `*` of wildcard hostnames in the name of their companion record, since a
+/// wildcard is only valid as the leftmost label
+const WILDCARD_LABEL: &str = "_wildcard";
+
+/// Ownership registry keeping a companion TXT record with an owner ID next to every managed
+/// hostname, similar to the TXT registry of Kubernetes external-dns.
+///
+/// The companion of `web.example.com` is `<prefix>web.example.com` with the value
+/// `"heritage=consul-external-dns,consul-external-dns/owner=<owner ID>"`. Hostnames claimed by
+/// another owner are never touched, while desired records of hostnames claimed by this owner are
+/// adopted even if they're missing in the Consul state.
+#[derive(Clone, Debug)]
+pub struct TxtRegistry {
+    owner_id: String,
+    prefix: String,
+}
+
+impl TxtRegistry {
+    pub fn new(owner_id: String, prefix: String) -> Self {
+        Self { owner_id, prefix }
+    }
+
+    /// Returns the companion record claiming `hostname` for this owner
+    pub fn companion(&self, hostname: &str) -> DnsRecord {
+        let hostname = match hostname.strip_prefix("*.") {
+            Some(name) => format!("{}.{}", WILDCARD_LABEL, name),
+            None => hostname.to_string(),
+        };
+        DnsRecord {
+            hostname: format!("{}{}", self.prefix, hostname),
+            type_: DnsType::TXT,
+            ttl: None,
+            value: format!("\"{},{}={}\"", HERITAGE, OWNER_KEY, self.owner_id),
+            service: None,
+            priority: None,
+        }
+    }
+
+    /// Returns the hostname claimed by a companion record and its owner, or `None` if the record
+    /// isn't a companion record
+    fn claim<'a>(&self, record: &'a DnsRecord) -> Option<(String, &'a str)> {
+        if record.type_ != DnsType::TXT {
+            return None;
+        }
+        let hostname = record.hostname.strip_prefix(&self.prefix)?;
+        let mut fields = record.value.trim().trim_matches('"').split(',');
+        if fields.next()? != HERITAGE {
+            return None;
+        }
+        let owner = fields.find_map(|field| field.strip_prefix(OWNER_KEY)?.strip_prefix('='))?;
+
+        let hostname = match hostname.strip_prefix(WILDCARD_LABEL) {
+            Some(name) if name.starts_with('.') => format!("*{}", name),
+            _ => hostname.to_string(),
+        };
+        Some((hostname, owner))
+    }
+
+    /// Returns the hostname a record belongs to, i.e. the claimed hostname for companion records
+    fn subject(&self, record: &DnsRecord) -> String {
+        match self.claim(record) {
+            Some((hostname, _)) => hostname,
+            None => record.hostname.clone(),
+        }
+    }
+
+    /// Returns the owners of the hostnames claimed by the companion records among the `live`
+    /// records of the zone. A hostname claimed by several owners counts as owned by this owner
+    /// if any of the claims is its own.
+    pub fn owners(&self, live: &[(String, DnsRecord)]) -> HashMap<String, String> {
+        let mut owners = HashMap::new();
+        for (_, record) in live {
+            if let Some((hostname, owner)) = self.claim(record) {
+                let current: &mut String = owners.entry(hostname).or_default();
+                if current.is_empty() || owner == self.owner_id {
+                    *current = owner.to_string();
+                }
+            }
+        }
+        owners
+    }
+
+    /// Adds a companion record for every hostname of the `desired` records
+    pub fn with_companions(&self, desired: &[DnsRecord]) -> Vec<DnsRecord> {
+        let hostnames: BTreeSet<&str> = desired
+            .iter()
+            .map(|record| record.hostname.as_str())
+            .collect();
+        desired
+            .iter()
+            .cloned()
+            .chain(
+                hostnames
+                    .into_iter()
+                    .map(|hostname| self.companion(hostname)),
+            )
+            .collect()
+    }
+
+    /// Adds the `live` records of hostnames claimed by this owner to the `state`, so that records
+    /// whose state was lost are managed again instead of being recreated.
+    ///
+    /// Only records with the hostname and type of a `desired` record, including its companion, are
+    /// adopted. Other records at a claimed hostname, e.g. a hand-made `TXT` record at the apex,
+    /// aren't managed by this tool and must never be deleted.
+    pub fn adopt(
+        &self,
+        mut state: HashMap<String, DnsRecord>,
+        desired: &[DnsRecord],
+        live: &[(String, DnsRecord)],
+    ) -> HashMap<String, DnsRecord> {
+        let owners = self.owners(live);
+        for (record_id, record) in live {
+            if state.contains_key(record_id)
+                || owners.get(&self.subject(record)) != Some(&self.owner_id)
+                || !desired
+                    .iter()
+                    .any(|d| d.hostname == record.hostname && d.type_ == record.type_)
+            {
+                continue;
+            }
+            info!(
+                "Adopting DNS record `{}` owned by `{}`",
+                record.hostname, self.owner_id
+            );
+            state.insert(record_id.clone(), record.clone());
+        }
+        state
+    }
+
+    /// Drops the changes of a plan to hostnames claimed by another owner in the `live` records
+    pub fn restrict(&self, mut plan: Plan, live: &[(String, DnsRecord)]) -> Plan {
+        let owners = self.owners(live);
+        let allowed = |record: &DnsRecord| {
+            let hostname = self.subject(record);
+            match owners.get(&hostname) {
+                Some(owner) if *owner != self.owner_id => {
+                    warn!(
+                        "Skipping DNS record `{}` because `{}` is owned by `{}`",
+                        record.hostname, hostname, owner
+                    );
+                    false
+                }
+                _ => true,
+            }
+        };
+        plan.creates.retain(|record| allowed(record));
+        plan.updates.retain(|update| allowed(&update.new));
+        plan.deletes.retain(|delete| allowed(&delete.record));
+        plan
+    }
+}
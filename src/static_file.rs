@@ -0,0 +1,89 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+use crate::{consul::DnsRecord, record_source::RecordSource};
+
+#[derive(Debug, Deserialize)]
+struct StaticRecords {
+    #[serde(default)]
+    records: Vec<DnsRecord>,
+}
+
+/// A record source reading DNS records from a TOML file, e.g.
+///
+/// ```toml
+/// [[records]]
+/// hostname = "example.com"
+/// type = "MX"
+/// value = "10 mail.example.com"
+/// ttl = 3600
+/// ```
+///
+/// The file is polled for changes in the given interval.
+pub struct StaticFileSource {
+    path: PathBuf,
+    poll_interval: Duration,
+    contents: Option<String>,
+}
+
+impl StaticFileSource {
+    pub fn new(path: PathBuf, poll_interval: Duration) -> Self {
+        Self {
+            path,
+            poll_interval,
+            contents: None,
+        }
+    }
+
+    fn parse(&self, contents: &str) -> Result<Vec<DnsRecord>> {
+        let static_records: StaticRecords = toml::from_str(contents)
+            .with_context(|| format!("Failed to parse records file {}", self.path.display()))?;
+        Ok(static_records.records)
+    }
+}
+
+#[async_trait]
+impl RecordSource for StaticFileSource {
+    async fn fetch_records(&mut self) -> Result<Vec<DnsRecord>> {
+        // An invalid file on startup is a configuration error, so we fail right away.
+        if self.contents.is_none() {
+            let contents = tokio::fs::read_to_string(&self.path)
+                .await
+                .with_context(|| format!("Failed to read records file {}", self.path.display()))?;
+            let records = self.parse(&contents)?;
+            self.contents = Some(contents);
+            return Ok(records);
+        }
+
+        // Later on we keep the previous records until the file is valid again, so editing the file
+        // can't accidentally remove all of its records.
+        loop {
+            sleep(self.poll_interval).await;
+
+            let contents = match tokio::fs::read_to_string(&self.path).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!("Failed to read records file {}: {}", self.path.display(), e);
+                    continue;
+                }
+            };
+            if self.contents.as_ref() == Some(&contents) {
+                continue;
+            }
+
+            match self.parse(&contents) {
+                Ok(records) => {
+                    info!("Records file {} has changed", self.path.display());
+                    self.contents = Some(contents);
+                    return Ok(records);
+                }
+                Err(e) => error!("{:#}", e),
+            }
+        }
+    }
+}
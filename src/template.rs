@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use tracing::error;
+
+use crate::consul::DnsRecord;
+
+/// Renders all `{{variable}}` placeholders in a template, e.g. `{{service}}.{{dc}}.example.com`.
+///
+/// Fails if the template references a variable that isn't defined.
+pub fn render(template: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("}}") else {
+            bail!("Unterminated placeholder in template `{}`", template);
+        };
+        let name = rest[..end].trim();
+        let Some(value) = variables.get(name) else {
+            bail!("Unknown variable `{}` in template `{}`", name, template);
+        };
+        rendered.push_str(value);
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Renders the hostnames of the given records as templates, skipping records whose hostname
+/// can't be rendered.
+pub(crate) fn render_hostnames(
+    records: Vec<DnsRecord>,
+    variables: &HashMap<String, String>,
+) -> Vec<DnsRecord> {
+    records
+        .into_iter()
+        .filter_map(|mut record| match render(&record.hostname, variables) {
+            Ok(hostname) => {
+                record.hostname = hostname;
+                Some(record)
+            }
+            Err(e) => {
+                error!("Failed to render hostname: {}", e);
+                None
+            }
+        })
+        .collect()
+}
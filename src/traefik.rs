@@ -0,0 +1,75 @@
+use std::net::IpAddr;
+
+use tracing::warn;
+
+use crate::{consul::DnsRecord, dns_trait::DnsType};
+
+/// Tag enabling Traefik for a service instance
+pub(crate) const TAG_ENABLE: &str = "traefik.enable=true";
+
+const ROUTER_PREFIX: &str = "traefik.http.routers.";
+
+/// Builds DNS records pointing at `target` for all hostnames matched by `Host()` rules in the
+/// Traefik router tags of a service instance.
+///
+/// The record type is derived from the target: IPv4 and IPv6 addresses result in `A` and `AAAA`
+/// records, anything else in a `CNAME` record.
+pub(crate) fn parse_traefik_tags(tags: &[String], target: &str) -> Vec<DnsRecord> {
+    let type_ = match target.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => DnsType::A,
+        Ok(IpAddr::V6(_)) => DnsType::AAAA,
+        Err(_) => DnsType::CNAME,
+    };
+
+    tags.iter()
+        .filter_map(|tag| {
+            // Router rules have the format `traefik.http.routers.<router>.rule=<rule>`
+            let (key, rule) = tag.strip_prefix(ROUTER_PREFIX)?.split_once('=')?;
+            key.strip_suffix(".rule")?;
+            Some(rule)
+        })
+        .flat_map(parse_host_rule)
+        .map(|hostname| DnsRecord {
+            hostname,
+            type_: type_.clone(),
+            ttl: None,
+            value: target.to_string(),
+            service: None,
+            priority: None,
+        })
+        .collect()
+}
+
+/// Extracts the hostnames of all `Host()` matchers in a Traefik rule, e.g.
+/// ``Host(`a.example.com`) || (Host(`b.example.com`) && PathPrefix(`/api`))``.
+fn parse_host_rule(rule: &str) -> Vec<String> {
+    const MATCHER: &str = "Host(";
+
+    let mut hostnames = Vec::new();
+    let mut rest = rule;
+    while let Some(start) = rest.find(MATCHER) {
+        // Skip matchers that merely end in `Host`, e.g. `ClientHost(`
+        let is_host_matcher = rest[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_ascii_alphanumeric());
+        rest = &rest[start + MATCHER.len()..];
+        let Some(end) = rest.find(')') else {
+            warn!("Unterminated Host matcher in Traefik rule: {}", rule);
+            break;
+        };
+        if is_host_matcher {
+            // Traefik v2 allows several comma-separated hostnames in a single matcher
+            hostnames.extend(
+                rest[..end]
+                    .split(',')
+                    .map(|host| host.trim().trim_matches(|c| c == '`' || c == '"'))
+                    .filter(|host| !host.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        rest = &rest[end..];
+    }
+
+    hostnames
+}
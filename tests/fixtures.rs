@@ -22,6 +22,8 @@ impl EnvironmentManager {
         EnvironmentManager
     }
 
+    // The agent is left running in the background and killed when the environment is dropped.
+    #[allow(clippy::zombie_processes)]
     pub async fn start_consul() -> Result<(), String> {
         Command::new("docker")
             .arg("run")
@@ -60,6 +62,8 @@ impl EnvironmentManager {
         Err("Consul did not start in time".into())
     }
 
+    // The agent is left running in the background and killed when the environment is dropped.
+    #[allow(clippy::zombie_processes)]
     pub async fn start_nomad() -> Result<(), String> {
         Command::new("sudo")
             .arg("nomad")
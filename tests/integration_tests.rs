@@ -4,20 +4,31 @@ mod mocks;
 #[cfg(test)]
 mod tests {
 
+    use std::collections::{BTreeSet, HashMap};
     use std::fs;
     use std::process::Command;
 
-    use consul_external_dns::config::HetznerConfig;
-    use consul_external_dns::config::{Config, DnsProvider};
-    use consul_external_dns::consul::{ConsulClient, DnsRecord};
+    use clap::Parser;
+    use consul_external_dns::config::{Command as CliCommand, Config, DnsProvider};
+    use consul_external_dns::config::{HetznerCloudConfig, HetznerConfig};
+    use consul_external_dns::conflict;
+    use consul_external_dns::consul::{ConsulClient, ConsulHttpOptions, DnsRecord};
     use consul_external_dns::dns_trait::{self, DnsProviderTrait, DnsType};
+    use consul_external_dns::hetzner_cloud::HetznerCloud;
     use consul_external_dns::hetzner_dns::HetznerDns;
+    use consul_external_dns::hostname;
+    use consul_external_dns::nomad::NomadSource;
+    use consul_external_dns::plan::{self, Delete, DeletionThreshold, Plan, Policy, Update};
+    use consul_external_dns::record_source::{RecordSource, RecordSources};
+    use consul_external_dns::registry::TxtRegistry;
+    use consul_external_dns::static_file::StaticFileSource;
     use fake::Fake;
     use mockito::Server;
     use reqwest::{Client, Url};
+    use rstest::rstest;
 
     use crate::fixtures::{self, EnvironmentManager};
-    use crate::mocks::{consul_mock, hetzner_mock};
+    use crate::mocks::{consul_mock, hetzner_cloud_mock, hetzner_mock, nomad_mock};
 
     // It uses the mockito library to mock the Hetzner service response and checks if the DNS record was created.
     #[tokio::test]
@@ -32,11 +43,14 @@ mod tests {
         let hetzner_dns = HetznerDns {
             config,
             reqwest_client: Client::new(),
+            zone_name: "example.com".to_string(),
         };
         let consul_dns_record = DnsRecord {
             hostname: "new.example.com".to_string(),
             type_: DnsType::A,
             value: "192.168.0.1".to_string(),
+            service: None,
+            priority: None,
             ttl: Some(300),
         };
 
@@ -44,7 +58,7 @@ mod tests {
             id: "fake_record_id".to_string(),
             zone_id: "fake_zone_id".to_string(),
             type_: DnsType::A,
-            name: "new.example.com".to_string(),
+            name: "new".to_string(),
             value: "192.168.0.1".to_string(),
             ttl: Some(300),
         };
@@ -59,11 +73,982 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    // It checks that hostnames are normalized to FQDNs inside the zone and made relative again.
+    #[rstest]
+    #[case("webapp", Some(("webapp.example.com", "webapp")))]
+    #[case("webapp.example.com", Some(("webapp.example.com", "webapp")))]
+    #[case("WebApp.Example.com.", Some(("webapp.example.com", "webapp")))]
+    #[case("@", Some(("example.com", "@")))]
+    #[case("example.com.", Some(("example.com", "@")))]
+    #[case("Bücher.example.com", Some(("xn--bcher-kva.example.com", "xn--bcher-kva")))]
+    #[case("xn--bcher-kva", Some(("xn--bcher-kva.example.com", "xn--bcher-kva")))]
+    #[case("api.staging", Some(("api.staging.example.com", "api.staging")))]
+    #[case("webapp.example.org.", None)]
+    #[case("webapp..example.com", None)]
+    fn test_normalize_hostname(#[case] hostname: &str, #[case] expected: Option<(&str, &str)>) {
+        let normalized = hostname::normalize(hostname, "example.com").ok();
+        assert_eq!(normalized.as_deref(), expected.map(|(fqdn, _)| fqdn));
+
+        if let Some((fqdn, relative)) = expected {
+            assert_eq!(hostname::relative(fqdn, "example.com."), relative);
+        }
+    }
+
+    // It checks that an existing record is updated in place with its new value and TTL.
+    #[tokio::test]
+    async fn test_update_dns_record() {
+        let mut server = fixtures::server().await;
+
+        let hetzner_dns = HetznerDns {
+            config: HetznerConfig {
+                dns_token: "fake_token".to_string(),
+                dns_zone_id: "fake_zone_id".to_string(),
+                api_url: url::Url::parse(&server.url()).expect("Invalid URL"),
+            },
+            reqwest_client: Client::new(),
+            zone_name: "example.com".to_string(),
+        };
+        let consul_dns_record = DnsRecord {
+            hostname: "web.example.com".to_string(),
+            type_: DnsType::A,
+            value: "192.168.0.2".to_string(),
+            ttl: Some(60),
+            service: Some("web".to_string()),
+            priority: None,
+        };
+
+        let update_mock = server
+            .mock("PUT", "/records/fake_record_id")
+            .match_header("Auth-API-Token", "fake_token")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "zone_id": "fake_zone_id",
+                "type": "A",
+                "name": "web",
+                "value": "192.168.0.2",
+                "ttl": 60,
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{"record": {"id": "fake_record_id", "zone_id": "fake_zone_id", "type": "A", "name": "web", "value": "192.168.0.2", "ttl": 60}}"#,
+            )
+            .create_async()
+            .await;
+
+        let record_id = hetzner_dns
+            .update_dns_record("fake_record_id", &consul_dns_record)
+            .await
+            .expect("Failed to update DNS record");
+
+        update_mock.assert();
+        assert_eq!(record_id, "fake_record_id");
+    }
+
+    fn record(hostname: &str, value: &str, ttl: Option<i32>, service: &str) -> DnsRecord {
+        DnsRecord {
+            hostname: hostname.to_string(),
+            type_: DnsType::A,
+            ttl,
+            value: value.to_string(),
+            service: Some(service.to_string()),
+            priority: None,
+        }
+    }
+
+    // It checks that a relative multi-label hostname stored by an earlier version matches the
+    // normalized desired record after an upgrade, so the live record isn't deleted.
+    #[test]
+    fn test_upgrade_keeps_relative_hostnames() {
+        let legacy = record("api.staging", "10.0.0.1", None, "api");
+        let mut state = HashMap::from([("1".to_string(), legacy.clone())]);
+        hostname::normalize_state(&mut state, "example.com");
+        let desired = hostname::normalize_records(vec![legacy], "example.com");
+
+        let plan = Plan::new(&desired, &state);
+
+        assert_eq!(desired[0].hostname, "api.staging.example.com");
+        assert!(plan.is_empty());
+    }
+
+    // It checks that internationalized targets of `CNAME` and `MX` records are converted to their
+    // ASCII form, while other values are kept.
+    #[rstest]
+    #[case(DnsType::CNAME, "Bücher.example.net.", "xn--bcher-kva.example.net.")]
+    #[case(DnsType::CNAME, "Web.example.net", "Web.example.net")]
+    #[case(DnsType::MX, "10 bücher.example.net", "10 xn--bcher-kva.example.net")]
+    #[case(DnsType::TXT, "bücher", "bücher")]
+    fn test_normalize_record_targets(
+        #[case] type_: DnsType,
+        #[case] value: &str,
+        #[case] expected: &str,
+    ) {
+        let records = vec![DnsRecord {
+            type_,
+            ..record("web", value, None, "web")
+        }];
+
+        let normalized = hostname::normalize_records(records, "example.com");
+
+        assert_eq!(normalized.len(), 1);
+        assert_eq!(normalized[0].hostname, "web.example.com");
+        assert_eq!(normalized[0].value, expected);
+    }
+
+    /// A record source returning the given results one after another, then blocking forever
+    struct ScriptedSource(std::collections::VecDeque<anyhow::Result<Vec<DnsRecord>>>);
+
+    #[async_trait::async_trait]
+    impl RecordSource for ScriptedSource {
+        async fn fetch_records(&mut self) -> anyhow::Result<Vec<DnsRecord>> {
+            match self.0.pop_front() {
+                Some(result) => result,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    // It checks that a record source failing after startup keeps its records and is retried,
+    // while a failure on startup is returned.
+    #[tokio::test]
+    async fn test_record_sources_retry() {
+        let web = record("web.example.com", "10.0.0.1", None, "web");
+        let api = record("api.example.com", "10.0.0.2", None, "api");
+        let mut sources = RecordSources::spawn(vec![Box::new(ScriptedSource(
+            [
+                Ok(vec![web.clone()]),
+                Err(anyhow::anyhow!("transient error")),
+                Ok(vec![api.clone()]),
+            ]
+            .into(),
+        ))]);
+        assert_eq!(sources.changed().await.unwrap(), [web]);
+        assert_eq!(sources.changed().await.unwrap(), [api]);
+
+        let mut sources = RecordSources::spawn(vec![Box::new(ScriptedSource(
+            [Err(anyhow::anyhow!("invalid configuration"))].into(),
+        ))]);
+        assert!(sources.changed().await.is_err());
+    }
+
+    // It checks that the plan creates new records, updates changed ones in place and deletes
+    // records that are no longer desired.
+    #[test]
+    fn test_plan() {
+        let current = HashMap::from([
+            (
+                "1".to_string(),
+                record("web.example.com", "10.0.0.1", None, "web"),
+            ),
+            (
+                "2".to_string(),
+                record("api.example.com", "10.0.0.2", None, "api"),
+            ),
+            (
+                "3".to_string(),
+                record("old.example.com", "10.0.0.3", None, "old"),
+            ),
+        ]);
+        let desired = [
+            record("web.example.com", "10.0.0.1", None, "web"),
+            record("api.example.com", "10.0.0.20", Some(60), "api"),
+            record("new.example.com", "10.0.0.4", None, "new"),
+        ];
+
+        let plan = Plan::new(&desired, &current);
+
+        assert_eq!(
+            plan,
+            Plan {
+                creates: vec![record("new.example.com", "10.0.0.4", None, "new")],
+                updates: vec![Update {
+                    record_id: "2".to_string(),
+                    old: record("api.example.com", "10.0.0.2", None, "api"),
+                    new: record("api.example.com", "10.0.0.20", Some(60), "api"),
+                }],
+                deletes: vec![Delete {
+                    record_id: "3".to_string(),
+                    record: record("old.example.com", "10.0.0.3", None, "old"),
+                    replaced: false,
+                }],
+            }
+        );
+        assert!(Plan::new(
+            &desired[..1],
+            &HashMap::from([("1".to_string(), desired[0].clone())])
+        )
+        .is_empty());
+    }
+
+    // It checks that policies restrict the changes of a plan.
+    #[rstest]
+    #[case(Policy::Sync, (1, 1, 1))]
+    #[case(Policy::UpsertOnly, (1, 1, 0))]
+    #[case(Policy::CreateOnly, (1, 0, 0))]
+    fn test_plan_policy(#[case] policy: Policy, #[case] expected: (usize, usize, usize)) {
+        let current = HashMap::from([
+            (
+                "1".to_string(),
+                record("api.example.com", "10.0.0.2", None, "api"),
+            ),
+            (
+                "2".to_string(),
+                record("old.example.com", "10.0.0.3", None, "old"),
+            ),
+        ]);
+        let desired = [
+            record("api.example.com", "10.0.0.20", None, "api"),
+            record("new.example.com", "10.0.0.4", None, "new"),
+        ];
+
+        let plan = Plan::new(&desired, &current).restrict(policy);
+
+        assert_eq!(
+            (plan.creates.len(), plan.updates.len(), plan.deletes.len()),
+            expected
+        );
+    }
+
+    // It checks that plans deleting too many records exceed the deletion threshold.
+    #[rstest]
+    #[case("2", 2, false)]
+    #[case("1", 2, true)]
+    #[case("50%", 2, false)]
+    #[case("49%", 2, true)]
+    #[case("100%", 4, false)]
+    fn test_plan_deletion_threshold(
+        #[case] threshold: &str,
+        #[case] deletes: usize,
+        #[case] expected: bool,
+    ) {
+        let current: HashMap<String, DnsRecord> = (0..4)
+            .map(|i| {
+                let hostname = format!("web{}.example.com", i);
+                (i.to_string(), record(&hostname, "10.0.0.1", None, "web"))
+            })
+            .collect();
+        let desired: Vec<DnsRecord> = current
+            .iter()
+            .filter(|(id, _)| id.parse::<usize>().unwrap() >= deletes)
+            .map(|(_, record)| record.clone())
+            .collect();
+
+        let threshold: DeletionThreshold = threshold.parse().expect("Invalid threshold");
+        let plan = Plan::new(&desired, &current);
+
+        assert_eq!(plan.deletes.len(), deletes);
+        assert_eq!(plan.exceeds(threshold, current.len()), expected);
+    }
+
+    // It checks that the changes of a plan are printed in human- and machine-readable form.
+    #[test]
+    fn test_plan_changes() {
+        let current = HashMap::from([(
+            "1".to_string(),
+            record("web.example.com", "10.0.0.1", None, "web"),
+        )]);
+        let desired = [record("web.example.com", "10.0.0.2", Some(60), "web")];
+
+        let plan = Plan::new(&desired, &current);
+        let changes = plan.changes();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].to_string(),
+            "update `web.example.com A 10.0.0.1` to `web.example.com 60 A 10.0.0.2`"
+        );
+        assert_eq!(
+            serde_json::to_value(&changes[0]).expect("Failed to serialize change"),
+            serde_json::json!({
+                "action": "update",
+                "record_id": "1",
+                "old": { "hostname": "web.example.com", "type": "A", "ttl": null, "value": "10.0.0.1", "service": "web" },
+                "new": { "hostname": "web.example.com", "type": "A", "ttl": 60, "value": "10.0.0.2", "service": "web" },
+            })
+        );
+    }
+
+    // It checks that the state reflects records changed or removed in the DNS provider.
+    #[test]
+    fn test_live_state() {
+        let state = HashMap::from([
+            (
+                "1".to_string(),
+                record("web.example.com", "10.0.0.1", None, "web"),
+            ),
+            (
+                "2".to_string(),
+                record("api.example.com", "10.0.0.2", None, "api"),
+            ),
+            (
+                "3".to_string(),
+                record("old.example.com", "10.0.0.3", None, "old"),
+            ),
+        ]);
+        let live = [
+            (
+                "1".to_string(),
+                record("web.example.com", "10.0.0.1", None, ""),
+            ),
+            (
+                "2".to_string(),
+                record("api.example.com", "10.0.0.9", Some(60), ""),
+            ),
+            (
+                "4".to_string(),
+                record("other.example.com", "10.0.0.4", None, ""),
+            ),
+        ];
+
+        let current = plan::live_state(&state, &live);
+
+        assert_eq!(
+            current,
+            HashMap::from([
+                (
+                    "1".to_string(),
+                    record("web.example.com", "10.0.0.1", None, "web")
+                ),
+                (
+                    "2".to_string(),
+                    record("api.example.com", "10.0.0.9", Some(60), "api")
+                ),
+            ])
+        );
+        assert_eq!(current["2"].service.as_deref(), Some("api"));
+    }
+
+    // It checks that a resync recreates records deleted out-of-band and forgets their old IDs.
+    #[test]
+    fn test_resync_recreates_deleted_records() {
+        let web = record("web.example.com", "10.0.0.1", None, "web");
+        let api = record("api.example.com", "10.0.0.2", None, "api");
+        let state = HashMap::from([
+            ("1".to_string(), web.clone()),
+            ("2".to_string(), api.clone()),
+        ]);
+        let live = [("1".to_string(), web.clone())];
+
+        let current = plan::live_state(&state, &live);
+        let plan = Plan::new(&[web.clone(), api.clone()], &current);
+
+        assert_eq!(current, HashMap::from([("1".to_string(), web)]));
+        assert_eq!(plan.creates, vec![api]);
+        assert!(plan.updates.is_empty());
+        assert!(plan.deletes.is_empty());
+    }
+
+    // It checks that the TXT registry adopts desired records claimed by its owner, and leaves
+    // unmanaged records and hostnames claimed by another owner alone.
+    #[test]
+    fn test_txt_registry() {
+        let registry = TxtRegistry::new("a".to_string(), "_external-dns.".to_string());
+        let foreign = TxtRegistry::new("b".to_string(), "_external-dns.".to_string());
+        let web = record("web.example.com", "10.0.0.1", None, "web");
+        let api = record("api.example.com", "10.0.0.2", None, "api");
+        let new = record("new.example.com", "10.0.0.3", None, "new");
+        let live = [
+            ("1".to_string(), web.clone()),
+            ("2".to_string(), registry.companion("web.example.com")),
+            (
+                "3".to_string(),
+                record("api.example.com", "10.0.0.9", None, ""),
+            ),
+            ("4".to_string(), foreign.companion("api.example.com")),
+            ("5".to_string(), registry.companion("*.example.com")),
+            // A hand-made record at a claimed hostname
+            (
+                "6".to_string(),
+                DnsRecord {
+                    type_: DnsType::TXT,
+                    ..record("web.example.com", "v=spf1 -all", None, "")
+                },
+            ),
+        ];
+
+        // The Consul state was lost
+        let desired = registry.with_companions(&[web.clone(), api, new.clone()]);
+        let current = registry.adopt(HashMap::new(), &desired, &live);
+        let plan = registry.restrict(Plan::new(&desired, &current), &live);
+
+        assert_eq!(
+            current.keys().map(String::as_str).collect::<BTreeSet<_>>(),
+            BTreeSet::from(["1", "2"])
+        );
+        assert_eq!(
+            plan.creates,
+            vec![new, registry.companion("new.example.com")]
+        );
+        assert!(plan.updates.is_empty());
+        assert!(plan.deletes.is_empty());
+        assert_eq!(
+            registry.companion("*.example.com").hostname,
+            "_external-dns._wildcard.example.com"
+        );
+        assert_eq!(
+            registry.owners(&live),
+            HashMap::from([
+                ("web.example.com".to_string(), "a".to_string()),
+                ("api.example.com".to_string(), "b".to_string()),
+                ("*.example.com".to_string(), "a".to_string()),
+            ])
+        );
+    }
+
+    fn cname(hostname: &str, value: &str, service: &str, priority: Option<i32>) -> DnsRecord {
+        DnsRecord {
+            type_: DnsType::CNAME,
+            priority,
+            ..record(hostname, value, None, service)
+        }
+    }
+
+    // It checks that conflicting records of a hostname are resolved by priority, then by the
+    // published record and finally by the service name.
+    #[rstest]
+    #[case::lowest_service_name(
+        vec![cname("x.example.com", "web.example.net", "web", None), record("x.example.com", "10.0.0.1", None, "api")],
+        &[],
+        vec![record("x.example.com", "10.0.0.1", None, "api")]
+    )]
+    #[case::published(
+        vec![cname("x.example.com", "web.example.net", "web", None), record("x.example.com", "10.0.0.1", None, "api")],
+        &["web"],
+        vec![cname("x.example.com", "web.example.net", "web", None)]
+    )]
+    #[case::priority(
+        vec![cname("x.example.com", "web.example.net", "web", Some(10)), record("x.example.com", "10.0.0.1", None, "api")],
+        &["api"],
+        vec![cname("x.example.com", "web.example.net", "web", None)]
+    )]
+    #[case::cnames(
+        vec![cname("x.example.com", "b.example.net", "b", None), cname("x.example.com", "a.example.net", "a", None)],
+        &[],
+        vec![cname("x.example.com", "a.example.net", "a", None)]
+    )]
+    #[case::no_cname(
+        vec![record("x.example.com", "10.0.0.1", None, "web"), record("x.example.com", "10.0.0.2", None, "api")],
+        &[],
+        vec![record("x.example.com", "10.0.0.1", None, "web"), record("x.example.com", "10.0.0.2", None, "api")]
+    )]
+    fn test_resolve_conflicts(
+        #[case] desired: Vec<DnsRecord>,
+        #[case] published: &[&str],
+        #[case] expected: Vec<DnsRecord>,
+    ) {
+        let published = desired
+            .iter()
+            .filter(|r| published.contains(&r.service.as_deref().unwrap_or_default()))
+            .enumerate()
+            .map(|(id, r)| (id.to_string(), r.clone()))
+            .collect();
+
+        assert_eq!(conflict::resolve(desired, &published), expected);
+    }
+
+    async fn hetzner_cloud(server: &mut mockito::ServerGuard) -> (HetznerCloud, mockito::Mock) {
+        let zone_mock = hetzner_mock::mock_get_zone(server, "zone", "example.com").await;
+        let args = [
+            "hetzner-cloud",
+            "--api-token=token",
+            "--dns-zone=zone",
+            &format!("--api-url={}", server.url()),
+        ];
+        let config = HetznerCloudConfig::try_parse_from(args).expect("Failed to parse arguments");
+        let provider = HetznerCloud::new(config)
+            .await
+            .expect("Failed to create provider");
+        (provider, zone_mock)
+    }
+
+    // It checks that a value of an existing RRset is added to it instead of failing, e.g. for the
+    // records of several instances of a service.
+    #[tokio::test]
+    async fn test_hetzner_cloud_create_adds_value() {
+        let mut server = fixtures::server().await;
+        let (provider, _zone_mock) = hetzner_cloud(&mut server).await;
+        let create_mock = hetzner_cloud_mock::mock_create_existing_rrset(&mut server, "zone").await;
+        let add_mock = hetzner_cloud_mock::mock_rrset_action(
+            &mut server,
+            "zone",
+            "web",
+            "A",
+            "add_records",
+            serde_json::json!({ "records": [{ "value": "10.0.0.2" }] }),
+        )
+        .await;
+
+        let record_id = provider
+            .create_dns_record(&record("web.example.com", "10.0.0.2", None, "web"))
+            .await
+            .expect("Failed to create DNS record");
+
+        create_mock.assert_async().await;
+        add_mock.assert_async().await;
+        assert_eq!(record_id, "web/A/10.0.0.2");
+    }
+
+    // It checks that updating a value of an RRset replaces only that value and leaves the TTL
+    // alone if it didn't change.
+    #[tokio::test]
+    async fn test_hetzner_cloud_update_replaces_value() {
+        let mut server = fixtures::server().await;
+        let (provider, _zone_mock) = hetzner_cloud(&mut server).await;
+        let get_mock =
+            hetzner_cloud_mock::mock_get_rrset(&mut server, "zone", "web", "A", Some(300)).await;
+        let add_mock = hetzner_cloud_mock::mock_rrset_action(
+            &mut server,
+            "zone",
+            "web",
+            "A",
+            "add_records",
+            serde_json::json!({ "records": [{ "value": "10.0.0.3" }] }),
+        )
+        .await;
+        let remove_mock = hetzner_cloud_mock::mock_rrset_action(
+            &mut server,
+            "zone",
+            "web",
+            "A",
+            "remove_records",
+            serde_json::json!({ "records": [{ "value": "10.0.0.1" }] }),
+        )
+        .await;
+        let ttl_mock = server
+            .mock("POST", "/zones/zone/rrsets/web/A/actions/change_ttl")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let record_id = provider
+            .update_dns_record(
+                "web/A/10.0.0.1",
+                &record("web.example.com", "10.0.0.3", Some(300), "web"),
+            )
+            .await
+            .expect("Failed to update DNS record");
+
+        get_mock.assert_async().await;
+        add_mock.assert_async().await;
+        remove_mock.assert_async().await;
+        ttl_mock.assert_async().await;
+        assert_eq!(record_id, "web/A/10.0.0.3");
+    }
+
+    // It checks that the companion records listed by a DNS provider claim their hostnames,
+    // including subdomains and wildcards.
+    #[tokio::test]
+    async fn test_txt_registry_through_provider() {
+        let mut server = fixtures::server().await;
+        let (provider, _zone_mock) = hetzner_cloud(&mut server).await;
+        let registry = TxtRegistry::new("a".to_string(), "_external-dns.".to_string());
+        let foreign = TxtRegistry::new("b".to_string(), "_external-dns.".to_string());
+        let rrset = |name: &str, type_: &str, value: &str| {
+            serde_json::json!({
+                "id": format!("{}/{}", name, type_),
+                "name": name,
+                "type": type_,
+                "records": [{ "value": value }],
+            })
+        };
+        let list_mock = hetzner_cloud_mock::mock_list_rrsets(
+            &mut server,
+            "zone",
+            serde_json::json!([
+                rrset("web", "A", "10.0.0.1"),
+                rrset(
+                    "_external-dns.web",
+                    "TXT",
+                    &registry.companion("web.example.com").value
+                ),
+                rrset("api.staging", "A", "10.0.0.9"),
+                rrset(
+                    "_external-dns.api.staging",
+                    "TXT",
+                    &foreign.companion("api.staging.example.com").value
+                ),
+                rrset(
+                    "_external-dns._wildcard",
+                    "TXT",
+                    &registry.companion("*.example.com").value
+                ),
+            ]),
+        )
+        .await;
+
+        let live = provider
+            .list_dns_records()
+            .await
+            .expect("Failed to list DNS records");
+        list_mock.assert_async().await;
+
+        assert_eq!(
+            registry.owners(&live),
+            HashMap::from([
+                ("web.example.com".to_string(), "a".to_string()),
+                ("api.staging.example.com".to_string(), "b".to_string()),
+                ("*.example.com".to_string(), "a".to_string()),
+            ])
+        );
+
+        // The Consul state was lost
+        let desired = registry.with_companions(&[
+            record("web.example.com", "10.0.0.1", None, "web"),
+            record("api.staging.example.com", "10.0.0.2", None, "api"),
+        ]);
+        let current = registry.adopt(HashMap::new(), &desired, &live);
+        let plan = registry.restrict(Plan::new(&desired, &current), &live);
+
+        assert_eq!(
+            current.keys().map(String::as_str).collect::<BTreeSet<_>>(),
+            BTreeSet::from(["web/A/10.0.0.1", "_external-dns.web/TXT/\"heritage=consul-external-dns,consul-external-dns/owner=a\""])
+        );
+        assert!(plan.is_empty());
+    }
+
+    // It checks that the `plan` and `apply` subcommands take the DNS provider as subcommand.
+    #[test]
+    fn test_parse_commands() {
+        let args = [
+            "consul-external-dns",
+            "hetzner",
+            "--dns-token=t",
+            "--dns-zone-id=z",
+        ];
+        let config = Config::try_parse_from(args).expect("Failed to parse arguments");
+        assert!(matches!(
+            config.command,
+            CliCommand::Run(DnsProvider::Hetzner(_))
+        ));
+
+        for command in ["plan", "apply"] {
+            let args = [
+                "consul-external-dns",
+                command,
+                "hetzner",
+                "--dns-token=t",
+                "--dns-zone-id=z",
+            ];
+            let config = Config::try_parse_from(args).expect("Failed to parse arguments");
+            assert!(matches!(
+                config.command.dns_provider(),
+                DnsProvider::Hetzner(_)
+            ));
+            assert!(!matches!(config.command, CliCommand::Run(_)));
+        }
+    }
+
+    /// A DNS provider failing to create records and recording all other calls
+    #[derive(Default)]
+    struct FailingProvider {
+        deleted: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DnsProviderTrait for FailingProvider {
+        fn zone_name(&self) -> &str {
+            "example.com"
+        }
+
+        async fn list_dns_records(&self) -> anyhow::Result<Vec<(String, DnsRecord)>> {
+            Ok(Vec::new())
+        }
+
+        async fn create_dns_record<'a>(&self, _: &'a DnsRecord) -> anyhow::Result<String> {
+            anyhow::bail!("creating records fails")
+        }
+
+        async fn update_dns_record<'a>(
+            &self,
+            record_id: &'a str,
+            _: &'a DnsRecord,
+        ) -> anyhow::Result<String> {
+            Ok(record_id.to_string())
+        }
+
+        async fn delete_dns_record<'a>(&self, record_id: &'a str) -> anyhow::Result<()> {
+            self.deleted.lock().unwrap().push(record_id.to_string());
+            Ok(())
+        }
+    }
+
+    // It checks that the old value of a hostname is kept if its new value couldn't be created.
+    #[tokio::test]
+    async fn test_plan_keeps_replaced_records() {
+        let current = HashMap::from([
+            (
+                "1".to_string(),
+                record("web.example.com", "10.0.0.1", None, "web-v1"),
+            ),
+            (
+                "2".to_string(),
+                record("old.example.com", "10.0.0.2", None, "old"),
+            ),
+        ]);
+        let desired = [record("web.example.com", "10.0.0.10", None, "web-v2")];
+
+        let plan = Plan::new(&desired, &current);
+        assert_eq!(plan.creates, desired);
+        assert!(plan.updates.is_empty());
+        assert_eq!(
+            plan.deletes
+                .iter()
+                .map(|delete| (delete.record_id.as_str(), delete.replaced))
+                .collect::<Vec<_>>(),
+            [("1", true), ("2", false)]
+        );
+
+        let provider = FailingProvider::default();
+        let mut state = current.clone();
+        plan.apply(&provider, &mut state).await;
+
+        assert_eq!(*provider.deleted.lock().unwrap(), ["2"]);
+        assert_eq!(state.keys().collect::<Vec<_>>(), ["1"]);
+    }
+
+    /// A DNS provider recording the changes made to it in order
+    #[derive(Default)]
+    struct RecordingProvider {
+        changes: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DnsProviderTrait for RecordingProvider {
+        fn zone_name(&self) -> &str {
+            "example.com"
+        }
+
+        async fn list_dns_records(&self) -> anyhow::Result<Vec<(String, DnsRecord)>> {
+            Ok(Vec::new())
+        }
+
+        async fn create_dns_record<'a>(&self, record: &'a DnsRecord) -> anyhow::Result<String> {
+            let mut changes = self.changes.lock().unwrap();
+            changes.push(format!("create {}", record.value));
+            Ok(format!("new-{}", changes.len()))
+        }
+
+        async fn update_dns_record<'a>(
+            &self,
+            record_id: &'a str,
+            _: &'a DnsRecord,
+        ) -> anyhow::Result<String> {
+            Ok(record_id.to_string())
+        }
+
+        async fn delete_dns_record<'a>(&self, record_id: &'a str) -> anyhow::Result<()> {
+            self.changes
+                .lock()
+                .unwrap()
+                .push(format!("delete {}", record_id));
+            Ok(())
+        }
+    }
+
+    // It checks that all old values of a hostname are deleted only after a new value was created,
+    // and kept if none of the new values could be created.
+    #[tokio::test]
+    async fn test_apply_deletes_replaced_records_after_create() {
+        let current = HashMap::from([
+            (
+                "1".to_string(),
+                record("web.example.com", "10.0.0.1", None, "web-v1"),
+            ),
+            (
+                "2".to_string(),
+                record("web.example.com", "10.0.0.2", None, "web-v1"),
+            ),
+        ]);
+        let desired = [record("web.example.com", "10.0.0.10", None, "web-v2")];
+        let plan = Plan::new(&desired, &current);
+
+        let provider = RecordingProvider::default();
+        let mut state = current.clone();
+        plan.apply(&provider, &mut state).await;
+        assert_eq!(
+            *provider.changes.lock().unwrap(),
+            ["create 10.0.0.10", "delete 1", "delete 2"]
+        );
+        assert_eq!(
+            state,
+            HashMap::from([("new-1".to_string(), desired[0].clone())])
+        );
+
+        let provider = FailingProvider::default();
+        let mut state = current.clone();
+        plan.apply(&provider, &mut state).await;
+        assert!(provider.deleted.lock().unwrap().is_empty());
+        assert_eq!(state, current);
+    }
+
+    /// A DNS provider applying whole plans at once like a batch API
+    #[derive(Default)]
+    struct BatchProvider {
+        plans: std::sync::Mutex<Vec<Plan>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DnsProviderTrait for BatchProvider {
+        fn zone_name(&self) -> &str {
+            "example.com"
+        }
+
+        async fn list_dns_records(&self) -> anyhow::Result<Vec<(String, DnsRecord)>> {
+            Ok(Vec::new())
+        }
+
+        async fn create_dns_record<'a>(&self, _: &'a DnsRecord) -> anyhow::Result<String> {
+            anyhow::bail!("records are only changed in batches")
+        }
+
+        async fn update_dns_record<'a>(
+            &self,
+            _: &'a str,
+            _: &'a DnsRecord,
+        ) -> anyhow::Result<String> {
+            anyhow::bail!("records are only changed in batches")
+        }
+
+        async fn delete_dns_record<'a>(&self, _: &'a str) -> anyhow::Result<()> {
+            anyhow::bail!("records are only changed in batches")
+        }
+
+        async fn apply_plan(&self, plan: &Plan, state: &mut HashMap<String, DnsRecord>) {
+            self.plans.lock().unwrap().push(plan.clone());
+            for (index, record) in plan.creates.iter().enumerate() {
+                state.insert(format!("batch-{}", index), record.clone());
+            }
+        }
+    }
+
+    // It checks that providers with batch APIs receive the whole plan at once.
+    #[tokio::test]
+    async fn test_apply_plan_in_batch() {
+        let desired = [
+            record("web.example.com", "10.0.0.1", None, "web"),
+            record("api.example.com", "10.0.0.2", None, "api"),
+        ];
+        let plan = Plan::new(&desired, &HashMap::new());
+
+        let provider = BatchProvider::default();
+        let mut state = HashMap::new();
+        plan.apply(&provider, &mut state).await;
+
+        assert_eq!(*provider.plans.lock().unwrap(), [plan]);
+        assert_eq!(state.len(), 2);
+    }
+
+    // It checks that the records of the zone are listed with FQDNs, skipping unsupported types.
+    #[tokio::test]
+    async fn test_list_dns_records() {
+        let mut server = fixtures::server().await;
+
+        let hetzner_dns = HetznerDns {
+            config: HetznerConfig {
+                dns_token: "fake_token".to_string(),
+                dns_zone_id: "fake_zone_id".to_string(),
+                api_url: url::Url::parse(&server.url()).expect("Invalid URL"),
+            },
+            reqwest_client: Client::new(),
+            zone_name: "example.com".to_string(),
+        };
+        let list_mock = server
+            .mock("GET", "/records")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("zone_id".into(), "fake_zone_id".into()),
+                mockito::Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .match_header("Auth-API-Token", "fake_token")
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "records": [
+                        { "id": "1", "type": "NS", "name": "@", "value": "ns1.example.com." },
+                        { "id": "2", "type": "A", "name": "web", "value": "10.0.0.1", "ttl": 60 },
+                        { "id": "3", "type": "TXT", "name": "@", "value": "v=spf1 -all" },
+                        { "id": "4", "type": "A", "name": "api.staging", "value": "10.0.0.2" },
+                    ],
+                    "meta": { "pagination": { "page": 1, "last_page": 1 } },
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let records = hetzner_dns
+            .list_dns_records()
+            .await
+            .expect("Failed to list DNS records");
+
+        list_mock.assert();
+        let records: Vec<(&str, &str, &str)> = records
+            .iter()
+            .map(|(id, record)| (id.as_str(), record.hostname.as_str(), record.value.as_str()))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("2", "web.example.com", "10.0.0.1"),
+                ("3", "example.com", "v=spf1 -all"),
+                ("4", "api.staging.example.com", "10.0.0.2")
+            ]
+        );
+    }
+
+    // It checks that the RRsets of a Hetzner Cloud zone are listed with FQDNs and one record per
+    // value, skipping unsupported types.
+    #[tokio::test]
+    async fn test_hetzner_cloud_list_dns_records() {
+        let mut server = fixtures::server().await;
+        let (provider, _zone_mock) = hetzner_cloud(&mut server).await;
+        let list_mock = hetzner_cloud_mock::mock_list_rrsets(
+            &mut server,
+            "zone",
+            serde_json::json!([
+                { "id": "@/NS", "name": "@", "type": "NS", "records": [{ "value": "ns1.example.com." }] },
+                { "id": "@/TXT", "name": "@", "type": "TXT", "records": [{ "value": "\"v=spf1 -all\"" }] },
+                {
+                    "id": "api.staging/A",
+                    "name": "api.staging",
+                    "type": "A",
+                    "ttl": 60,
+                    "records": [{ "value": "10.0.0.1" }, { "value": "10.0.0.2" }],
+                },
+            ]),
+        )
+        .await;
+
+        let records = provider
+            .list_dns_records()
+            .await
+            .expect("Failed to list DNS records");
+
+        list_mock.assert_async().await;
+        let records: Vec<(&str, &str, &str)> = records
+            .iter()
+            .map(|(id, record)| (id.as_str(), record.hostname.as_str(), record.value.as_str()))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("@/TXT/\"v=spf1 -all\"", "example.com", "\"v=spf1 -all\""),
+                (
+                    "api.staging/A/10.0.0.1",
+                    "api.staging.example.com",
+                    "10.0.0.1"
+                ),
+                (
+                    "api.staging/A/10.0.0.2",
+                    "api.staging.example.com",
+                    "10.0.0.2"
+                ),
+            ]
+        );
+    }
+
     // It uses the mockito library to mock the Consul service response and checks if the tags are fetched correctly.
     #[tokio::test]
     async fn test_get_dns_tags() {
         let mut server = fixtures::server().await;
-        let get_mock_consul = consul_mock::mock_get_consul_services(&mut server).await;
+        let get_mocks_consul = consul_mock::mock_get_consul_services(&mut server).await;
 
         let parsed_url = match Url::parse(&server.url()) {
             Ok(url) => url,
@@ -90,17 +1075,46 @@ mod tests {
         };
 
         let config = Config {
-            dns_provider: DnsProvider::Hetzner(HetznerConfig {
+            command: CliCommand::Run(DnsProvider::Hetzner(HetznerConfig {
                 dns_token: "fake".to_string(),
                 dns_zone_id: "fake".to_string(),
                 api_url: url::Url::parse(&server.url()).expect("Invalid URL"),
-            }),
+            })),
             consul_address: url::Url::parse(format!("http://{}:{}", hostname, port).as_str())
                 .expect("Invalid URL"),
+            consul_datacenters: vec![],
+            consul_namespace: None,
+            consul_partition: None,
+            consul_token: None,
+            consul_token_file: None,
+            consul_ca_cert: None,
+            consul_client_cert: None,
+            consul_client_key: None,
+            consul_tls_server_name: None,
+            policy: Policy::Sync,
+            max_deletions: None,
+            allow_mass_deletion: false,
+            dry_run: false,
+            resync_interval: std::time::Duration::from_secs(300),
+            txt_owner_id: None,
+            txt_prefix: "_external-dns.".to_string(),
+            consul_passing_only: false,
+            traefik_target: None,
+            consul_kv_prefix: None,
+            consul_gateways: false,
+            nomad_address: None,
+            nomad_token: None,
+            records_file: None,
+            records_file_poll_interval: std::time::Duration::from_secs(10),
         };
 
-        let consul_client =
-            ConsulClient::new(config.consul_address, None).expect("Failed to create Consul client");
+        let consul_client = ConsulClient::new(
+            config.consul_address,
+            None,
+            config.consul_passing_only,
+            config.traefik_target,
+        )
+        .expect("Failed to create Consul client");
 
         let mut consul_index: Option<String> = None;
 
@@ -112,19 +1126,742 @@ mod tests {
             }
         };
 
-        get_mock_consul.assert();
-        assert_eq!(dns_tags.len(), 4);
+        get_mocks_consul.iter().for_each(|mock| mock.assert());
+        assert_eq!(dns_tags.len(), 3);
 
-        let has_correct_value = dns_tags.iter().any(|tag| tag.value == "192.168.1.102");
+        let has_correct_value = dns_tags.iter().any(|tag| tag.value == "192.168.1.103");
         assert!(
             has_correct_value,
-            "No tag has the expected value of '192.168.1.102'"
+            "No tag has the expected value of '192.168.1.103'"
+        );
+
+        // `external-dns.id3.enable=false` suppresses the record of that identifier
+        let has_disabled_value = dns_tags.iter().any(|tag| tag.value == "192.168.1.102");
+        assert!(
+            !has_disabled_value,
+            "The disabled tag with the value '192.168.1.102' was published"
+        );
+    }
+
+    // It checks that only services with passing instances contribute DNS records.
+    #[tokio::test]
+    async fn test_get_dns_tags_passing_only() {
+        let mut server = fixtures::server().await;
+        let healthy_tags: &[&str] = &[
+            "external-dns.web.hostname=web.example.com",
+            "external-dns.web.type=A",
+            "external-dns.web.value=192.168.1.10",
+            "external-dns.enable=true",
+        ];
+        let critical_tags: &[&str] = &[
+            "external-dns.api.hostname=api.example.com",
+            "external-dns.api.type=A",
+            "external-dns.api.value=192.168.1.20",
+            "external-dns.enable=true",
+        ];
+        let catalog_mock = consul_mock::mock_get_catalog_services(
+            &mut server,
+            &[("web", healthy_tags), ("api", critical_tags)],
+        )
+        .await;
+        let web_mock =
+            consul_mock::mock_get_service_instances(&mut server, "web", &[healthy_tags], true)
+                .await;
+        let api_mock = consul_mock::mock_get_service_instances(&mut server, "api", &[], true).await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            true,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+
+        catalog_mock.assert();
+        web_mock.assert();
+        api_mock.assert();
+        assert_eq!(
+            dns_tags,
+            vec![DnsRecord {
+                hostname: "web.example.com".to_string(),
+                type_: DnsType::A,
+                ttl: None,
+                value: "192.168.1.10".to_string(),
+                service: Some("web".to_string()),
+                priority: None,
+            }]
+        );
+        assert_eq!(consul_index.as_deref(), Some("10"));
+    }
+
+    // It checks that every instance of a service contributes its own DNS record.
+    #[tokio::test]
+    async fn test_get_dns_tags_per_instance() {
+        let mut server = fixtures::server().await;
+        let instance_tags = |value: &'static str| -> Vec<&'static str> {
+            vec![
+                "external-dns.web.hostname=web.example.com",
+                "external-dns.web.type=A",
+                value,
+                "external-dns.enable=true",
+            ]
+        };
+        let instance_1 = instance_tags("external-dns.web.value=192.168.1.10");
+        let instance_2 = instance_tags("external-dns.web.value=192.168.1.11");
+        let catalog_tags = [instance_1.clone(), instance_2.clone()].concat();
+        let catalog_mock =
+            consul_mock::mock_get_catalog_services(&mut server, &[("web", &catalog_tags)]).await;
+        // The third instance duplicates the first one and must not lead to a duplicate record
+        let instances_mock = consul_mock::mock_get_service_instances(
+            &mut server,
+            "web",
+            &[&instance_1, &instance_2, &instance_1],
+            false,
+        )
+        .await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let mut dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+        dns_tags.sort_by(|a, b| a.value.cmp(&b.value));
+
+        catalog_mock.assert();
+        instances_mock.assert();
+        let values: Vec<&str> = dns_tags.iter().map(|tag| tag.value.as_str()).collect();
+        assert_eq!(values, ["192.168.1.10", "192.168.1.11"]);
+    }
+
+    // It checks that DNS records can be declared in the metadata of a service instance.
+    #[tokio::test]
+    async fn test_get_dns_meta() {
+        let mut server = fixtures::server().await;
+        let catalog_mock =
+            consul_mock::mock_get_catalog_services(&mut server, &[("web", &[])]).await;
+        let instances_mock = consul_mock::mock_get_service_instances_with_meta(
+            &mut server,
+            "web",
+            &[&[
+                ("external-dns-enable", "true"),
+                ("external-dns-hostname", "web.example.com"),
+                ("external-dns-type", "A"),
+                ("external-dns-value", "192.168.1.10"),
+                (
+                    "external-dns",
+                    r#"[{"hostname":"api.example.com","type":"CNAME","value":"web.example.com","ttl":60}]"#,
+                ),
+            ]],
+        )
+        .await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let mut dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+        dns_tags.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+
+        catalog_mock.assert();
+        instances_mock.assert();
+        assert_eq!(
+            dns_tags,
+            vec![
+                DnsRecord {
+                    hostname: "api.example.com".to_string(),
+                    type_: DnsType::CNAME,
+                    ttl: Some(60),
+                    value: "web.example.com".to_string(),
+                    service: Some("web".to_string()),
+                    priority: None,
+                },
+                DnsRecord {
+                    hostname: "web.example.com".to_string(),
+                    type_: DnsType::A,
+                    ttl: None,
+                    value: "192.168.1.10".to_string(),
+                    service: Some("web".to_string()),
+                    priority: None,
+                },
+            ]
+        );
+        // The service isn't part of the equality of records
+        assert!(dns_tags
+            .iter()
+            .all(|tag| tag.service.as_deref() == Some("web")));
+    }
+
+    // It checks that the hostnames of Traefik router rules are published pointing at the target.
+    #[tokio::test]
+    async fn test_get_traefik_hosts() {
+        let mut server = fixtures::server().await;
+        let tags: &[&str] = &[
+            "traefik.enable=true",
+            "traefik.http.routers.web.rule=Host(`a.example.com`) || (Host(`b.example.com`) && PathPrefix(`/api`))",
+            "traefik.http.routers.web.entrypoints=https",
+        ];
+        let catalog_mock =
+            consul_mock::mock_get_catalog_services(&mut server, &[("web", tags)]).await;
+        let instances_mock =
+            consul_mock::mock_get_service_instances(&mut server, "web", &[tags], false).await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            Some("ingress.example.com".to_string()),
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let mut dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+        dns_tags.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+
+        catalog_mock.assert();
+        instances_mock.assert();
+        let expected: Vec<DnsRecord> = ["a.example.com", "b.example.com"]
+            .into_iter()
+            .map(|hostname| DnsRecord {
+                hostname: hostname.to_string(),
+                type_: DnsType::CNAME,
+                ttl: None,
+                value: "ingress.example.com".to_string(),
+                service: Some("web".to_string()),
+                priority: None,
+            })
+            .collect();
+        assert_eq!(dns_tags, expected);
+    }
+
+    // It checks that the tags of services registered with Nomad's native service discovery are
+    // parsed per allocation.
+    #[tokio::test]
+    async fn test_get_nomad_dns_tags() {
+        let mut server = fixtures::server().await;
+        let allocation_tags = |value: &'static str| -> Vec<&'static str> {
+            vec![
+                "external-dns.web.hostname=web.example.com",
+                "external-dns.web.type=A",
+                value,
+                "external-dns.enable=true",
+            ]
+        };
+        let mocks = nomad_mock::mock_get_nomad_service(
+            &mut server,
+            "web",
+            &[
+                &allocation_tags("external-dns.web.value=192.168.1.10"),
+                &allocation_tags("external-dns.web.value=192.168.1.11"),
+            ],
+        )
+        .await;
+
+        let mut nomad_source =
+            NomadSource::new(Url::parse(&server.url()).expect("Invalid URL"), None, None)
+                .expect("Failed to create Nomad source");
+
+        let mut dns_records = nomad_source
+            .fetch_records()
+            .await
+            .expect("Failed to fetch Nomad DNS records");
+        dns_records.sort_by(|a, b| a.value.cmp(&b.value));
+
+        mocks.iter().for_each(|mock| mock.assert());
+        let values: Vec<&str> = dns_records.iter().map(|tag| tag.value.as_str()).collect();
+        assert_eq!(values, ["192.168.1.10", "192.168.1.11"]);
+    }
+
+    // It checks that static DNS records are read from a records file.
+    #[tokio::test]
+    async fn test_get_static_file_records() {
+        let path = std::env::temp_dir().join(format!("records_{}.toml", (10..20).fake::<String>()));
+        fs::write(
+            &path,
+            r#"
+            [[records]]
+            hostname = "example.com"
+            type = "MX"
+            value = "10 mail.example.com"
+            ttl = 3600
+
+            [[records]]
+            hostname = "example.com"
+            type = "TXT"
+            value = "verification=abc"
+            "#,
+        )
+        .expect("Failed to write records file");
+
+        let mut file_source =
+            StaticFileSource::new(path.clone(), std::time::Duration::from_secs(10));
+        let records = file_source.fetch_records().await;
+        fs::remove_file(&path).expect("Failed to remove records file");
+
+        assert_eq!(
+            records.expect("Failed to read records file"),
+            vec![
+                DnsRecord {
+                    hostname: "example.com".to_string(),
+                    type_: DnsType::MX,
+                    ttl: Some(3600),
+                    value: "10 mail.example.com".to_string(),
+                    service: None,
+                    priority: None,
+                },
+                DnsRecord {
+                    hostname: "example.com".to_string(),
+                    type_: DnsType::TXT,
+                    ttl: None,
+                    value: "verification=abc".to_string(),
+                    service: None,
+                    priority: None,
+                },
+            ]
+        );
+    }
+
+    // It checks that DNS records declared under a Consul KV prefix are read, skipping invalid keys.
+    #[tokio::test]
+    async fn test_get_kv_records() {
+        let mut server = fixtures::server().await;
+        let kv_mock = consul_mock::mock_get_kv_prefix(
+            &mut server,
+            "external-dns/records/",
+            &[
+                (
+                    "apex",
+                    r#"{"hostname":"example.com","type":"A","value":"192.168.1.10","ttl":300}"#,
+                ),
+                (
+                    "mail",
+                    r#"[{"hostname":"example.com","type":"MX","value":"10 mail.example.com"}]"#,
+                ),
+                ("invalid", "not json"),
+            ],
+        )
+        .await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let mut dns_records = consul_client
+            .fetch_kv_records("external-dns/records/", &mut consul_index)
+            .await
+            .expect("Failed to fetch Consul KV records");
+        dns_records.sort_by(|a, b| a.value.cmp(&b.value));
+
+        kv_mock.assert();
+        assert_eq!(
+            dns_records,
+            vec![
+                DnsRecord {
+                    hostname: "example.com".to_string(),
+                    type_: DnsType::MX,
+                    ttl: None,
+                    value: "10 mail.example.com".to_string(),
+                    service: None,
+                    priority: None,
+                },
+                DnsRecord {
+                    hostname: "example.com".to_string(),
+                    type_: DnsType::A,
+                    ttl: Some(300),
+                    value: "192.168.1.10".to_string(),
+                    service: None,
+                    priority: None,
+                },
+            ]
+        );
+        assert_eq!(consul_index.as_deref(), Some("10"));
+    }
+
+    // It checks that the hosts of ingress and API gateways are published pointing at their
+    // instances.
+    #[tokio::test]
+    async fn test_get_gateway_records() {
+        let mut server = fixtures::server().await;
+        let mocks = [
+            consul_mock::mock_get_config_entries(
+                &mut server,
+                "ingress-gateway",
+                serde_json::json!([{
+                    "Kind": "ingress-gateway",
+                    "Name": "ingress",
+                    "Listeners": [{
+                        "Port": 8080,
+                        "Services": [{ "Name": "web", "Hosts": ["web.example.com"] }],
+                    }],
+                }]),
+            )
+            .await,
+            consul_mock::mock_get_config_entries(
+                &mut server,
+                "api-gateway",
+                serde_json::json!([{ "Kind": "api-gateway", "Name": "api", "Listeners": [] }]),
+            )
+            .await,
+            consul_mock::mock_get_config_entries(
+                &mut server,
+                "http-route",
+                serde_json::json!([{
+                    "Kind": "http-route",
+                    "Name": "api-route",
+                    "Hostnames": ["api.example.com"],
+                    "Parents": [{ "Kind": "api-gateway", "Name": "api" }],
+                }]),
+            )
+            .await,
+            consul_mock::mock_get_gateway_instances(&mut server, "ingress", &["192.168.1.10"])
+                .await,
+            consul_mock::mock_get_gateway_instances(&mut server, "api", &["2001:db8::1"]).await,
+        ];
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let mut dns_records = consul_client
+            .fetch_gateway_records(&mut consul_index)
+            .await
+            .expect("Failed to fetch gateway records");
+        dns_records.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+
+        mocks.iter().for_each(|mock| mock.assert());
+        assert_eq!(
+            dns_records,
+            vec![
+                DnsRecord {
+                    hostname: "api.example.com".to_string(),
+                    type_: DnsType::AAAA,
+                    ttl: None,
+                    value: "2001:db8::1".to_string(),
+                    service: Some("api".to_string()),
+                    priority: None,
+                },
+                DnsRecord {
+                    hostname: "web.example.com".to_string(),
+                    type_: DnsType::A,
+                    ttl: None,
+                    value: "192.168.1.10".to_string(),
+                    service: Some("ingress".to_string()),
+                    priority: None,
+                },
+            ]
+        );
+    }
+
+    // It checks that hostname templates are rendered with the service and datacenter names.
+    #[tokio::test]
+    async fn test_get_dns_tags_hostname_template() {
+        let mut server = fixtures::server().await;
+        let tags: &[&str] = &[
+            "external-dns.web.hostname={{service}}.{{ dc }}.example.com",
+            "external-dns.web.type=A",
+            "external-dns.web.value=192.168.1.10",
+            "external-dns.enable=true",
+        ];
+        let catalog_mock =
+            consul_mock::mock_get_catalog_services(&mut server, &[("web", tags)]).await;
+        let instances_mock =
+            consul_mock::mock_get_service_instances(&mut server, "web", &[tags], false).await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            Some("eu1".to_string()),
+            false,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+
+        catalog_mock.assert();
+        instances_mock.assert();
+        let hostnames: Vec<&str> = dns_tags.iter().map(|tag| tag.hostname.as_str()).collect();
+        assert_eq!(hostnames, ["web.eu1.example.com"]);
+    }
+
+    // It checks that hostname templates are rendered with the node, metadata and index of each
+    // instance.
+    #[tokio::test]
+    async fn test_get_dns_tags_instance_template() {
+        let mut server = fixtures::server().await;
+        let tags: &[&str] = &[
+            "external-dns.web.hostname={{node}}.{{meta.env}}.{{service}}-{{index}}.example.com",
+            "external-dns.web.type=A",
+            "external-dns.web.value=192.168.1.10",
+            "external-dns.enable=true",
+        ];
+        let catalog_mock =
+            consul_mock::mock_get_catalog_services(&mut server, &[("web", tags)]).await;
+        let instances = ["node2", "node1"]
+            .iter()
+            .map(|node| {
+                serde_json::json!({
+                    "Node": { "Node": node },
+                    "Service": { "ID": "web", "Tags": tags, "Meta": { "env": "prod" } },
+                })
+            })
+            .collect::<Vec<_>>();
+        let instances_mock = server
+            .mock("GET", "/v1/health/service/web")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::Value::Array(instances).to_string())
+            .create_async()
+            .await;
+
+        let consul_client = ConsulClient::new(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            None,
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+
+        catalog_mock.assert();
+        instances_mock.assert();
+        let mut hostnames: Vec<&str> = dns_tags.iter().map(|tag| tag.hostname.as_str()).collect();
+        hostnames.sort();
+        assert_eq!(
+            hostnames,
+            [
+                "node1.prod.web-0.example.com",
+                "node2.prod.web-1.example.com"
+            ]
+        );
+    }
+
+    // It checks that the services of all namespaces are published for the wildcard namespace.
+    #[tokio::test]
+    async fn test_get_dns_tags_all_namespaces() {
+        let mut server = fixtures::server().await;
+        let tags: &[&str] = &[
+            "external-dns.web.hostname={{service}}.{{namespace}}.example.com",
+            "external-dns.web.type=A",
+            "external-dns.web.value=192.168.1.10",
+            "external-dns.enable=true",
+        ];
+        let namespaces_mock = server
+            .mock("GET", "/v1/namespaces")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"[{ "Name": "default" }, { "Name": "team-a" }]"#)
+            .create_async()
+            .await;
+        let default_mock = server
+            .mock("GET", "/v1/catalog/services")
+            .match_query(mockito::Matcher::UrlEncoded("ns".into(), "default".into()))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body("{}")
+            .create_async()
+            .await;
+        let team_mock = server
+            .mock("GET", "/v1/catalog/services")
+            .match_query(mockito::Matcher::UrlEncoded("ns".into(), "team-a".into()))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::json!({ "web": tags }).to_string())
+            .create_async()
+            .await;
+        let instances_mock =
+            consul_mock::mock_get_service_instances(&mut server, "web", &[tags], false).await;
+
+        let consul_client = ConsulClient {
+            namespace: Some("*".to_string()),
+            ..ConsulClient::new(
+                Url::parse(&server.url()).expect("Invalid URL"),
+                None,
+                false,
+                None,
+            )
+            .expect("Failed to create Consul client")
+        };
+
+        let mut consul_index: Option<String> = None;
+        let dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+
+        namespaces_mock.assert();
+        default_mock.assert();
+        team_mock.assert();
+        instances_mock.assert();
+        let hostnames: Vec<&str> = dns_tags.iter().map(|tag| tag.hostname.as_str()).collect();
+        assert_eq!(hostnames, ["web.team-a.example.com"]);
+    }
+
+    // It checks that the ACL token is sent and the TLS server name is used as host of the requests.
+    #[tokio::test]
+    async fn test_get_dns_tags_http_options() {
+        let mut server = fixtures::server().await;
+        let tags: &[&str] = &[
+            "external-dns.web.hostname=web.example.com",
+            "external-dns.web.type=A",
+            "external-dns.web.value=192.168.1.10",
+            "external-dns.enable=true",
+        ];
+        let catalog_mock = server
+            .mock("GET", "/v1/catalog/services")
+            .match_query(mockito::Matcher::Any)
+            .match_header("X-Consul-Token", "secret")
+            .match_header("Host", mockito::Matcher::Regex("^consul.service:".into()))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::json!({ "web": tags }).to_string())
+            .create_async()
+            .await;
+        let instances_mock =
+            consul_mock::mock_get_service_instances(&mut server, "web", &[tags], false).await;
+
+        let consul_client = ConsulClient::with_options(
+            Url::parse(&server.url()).expect("Invalid URL"),
+            None,
+            false,
+            None,
+            &ConsulHttpOptions {
+                token: Some("secret".to_string()),
+                tls_server_name: Some("consul.service".to_string()),
+                ..Default::default()
+            },
+        )
+        .expect("Failed to create Consul client");
+
+        let mut consul_index: Option<String> = None;
+        let dns_tags = consul_client
+            .fetch_service_tags(&mut consul_index)
+            .await
+            .expect("Failed to fetch Consul DNS tags");
+
+        catalog_mock.assert();
+        instances_mock.assert();
+        assert_eq!(dns_tags.len(), 1);
+    }
+
+    // It runs the `plan` subcommand against a zone that's in line with the services, including
+    // a multi-label hostname, and checks that it doesn't report any drift.
+    #[tokio::test]
+    async fn test_plan_without_drift() {
+        let mut server = fixtures::server().await;
+        let _zone_mock = hetzner_mock::mock_get_zone(&mut server, "zone", "example.com").await;
+        let _list_mock = hetzner_cloud_mock::mock_list_rrsets(
+            &mut server,
+            "zone",
+            serde_json::json!([
+                { "id": "@/NS", "name": "@", "type": "NS", "records": [{ "value": "ns1.example.com." }] },
+                {
+                    "id": "api.staging/A",
+                    "name": "api.staging",
+                    "type": "A",
+                    "ttl": 60,
+                    "records": [{ "value": "10.0.0.1" }],
+                },
+            ]),
+        )
+        .await;
+        let _state_mock = consul_mock::mock_get_state(
+            &mut server,
+            serde_json::json!({
+                "api.staging/A/10.0.0.1": {
+                    "hostname": "api.staging.example.com",
+                    "type": "A",
+                    "ttl": 60,
+                    "value": "10.0.0.1",
+                },
+            }),
+        )
+        .await;
+        let tags: &[&str] = &[
+            "external-dns.enable=true",
+            "external-dns.api.hostname=api.staging",
+            "external-dns.api.type=A",
+            "external-dns.api.value=10.0.0.1",
+            "external-dns.api.ttl=60",
+        ];
+        let _services_mock =
+            consul_mock::mock_get_catalog_services(&mut server, &[("api", tags)]).await;
+        let _instances_mock =
+            consul_mock::mock_get_service_instances(&mut server, "api", &[tags], false).await;
+
+        let url = server.url();
+        let output = tokio::task::spawn_blocking(move || {
+            Command::new(env!("CARGO_BIN_EXE_consul_external_dns"))
+                .env_clear()
+                .args([
+                    &format!("--consul-address={}", url),
+                    "plan",
+                    "hetzner-cloud",
+                    "--api-token=token",
+                    "--dns-zone=zone",
+                    &format!("--api-url={}", url),
+                ])
+                .output()
+        })
+        .await
+        .unwrap()
+        .expect("Failed to run the plan subcommand");
+
+        assert_eq!(
+            output.status.code(),
+            Some(0),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
         );
     }
 
     // It will start Consul and Nomad in dev mode, run the Nomad job, and check if the DNS record was created.
     // This is an end-to-end test that checks if the application works as expected.
     #[tokio::test]
+    #[allow(clippy::zombie_processes)]
     async fn test_end_to_end() {
         let _env_manager = EnvironmentManager::new().await;
 
@@ -160,6 +1897,7 @@ mod tests {
                 .await;
         hetzner_mock::mock_get_dns_records(&mut mock_hetzner_server, "test_zone_id", "test_token")
             .await;
+        hetzner_mock::mock_get_zone(&mut mock_hetzner_server, "test_zone_id", "example.com").await;
 
         // Sleep for 5 seconds to allow the Consul and Nomad servers to start
         std::thread::sleep(std::time::Duration::from_secs(5));
@@ -1,46 +1,251 @@
 use mockito::ServerGuard;
 
-pub async fn mock_get_consul_services(server: &mut ServerGuard) -> mockito::Mock {
+/// Services registered in the mocked Consul catalog, each with the tags of its instances.
+const CONSUL_SERVICES: &[(&str, &[&[&str]])] = &[
+    ("consul", &[]),
+    ("redis", &[]),
+    (
+        "app-rev-mr1",
+        &[&[
+            "external-dns.id1.hostname=example.com",
+            "external-dns.id1.type=A",
+            "external-dns.id1.value=192.168.1.100",
+            "external-dns.id1.ttl=300",
+            "external-dns.id1.enable=true",
+            "external-dns.enable=true",
+        ]],
+    ),
+    (
+        "app-rev-mr2",
+        &[&[
+            "external-dns.id2.hostname=example.com",
+            "external-dns.id2.type=A",
+            "external-dns.id2.value=192.168.1.101",
+            "external-dns.id2.ttl=300",
+            "external-dns.id2.enable=true",
+            "external-dns.enable=true",
+        ]],
+    ),
+    (
+        "app-rev-mr3",
+        &[&[
+            "external-dns.id3.hostname=example.com",
+            "external-dns.id3.type=A",
+            "external-dns.id3.value=192.168.1.102",
+            "external-dns.id3.ttl=300",
+            "external-dns.id3.enable=false",
+            "external-dns.enable=true",
+        ]],
+    ),
+    (
+        "app-rev-mr4",
+        &[&[
+            "external-dns.id4.hostname=example.com",
+            "external-dns.id4.type=A",
+            "external-dns.id4.value=192.168.1.103",
+            "external-dns.id4.ttl=300",
+            "external-dns.id4.enable=true",
+            "external-dns.enable=true",
+        ]],
+    ),
+];
+
+/// Mocks the catalog and the instances of all services in [`CONSUL_SERVICES`].
+pub async fn mock_get_consul_services(server: &mut ServerGuard) -> Vec<mockito::Mock> {
     println!("Mocking consul services");
+    let services: Vec<(&str, Vec<&str>)> = CONSUL_SERVICES
+        .iter()
+        .map(|(name, instances)| (*name, instances.concat()))
+        .collect();
+    let services: Vec<(&str, &[&str])> = services
+        .iter()
+        .map(|(name, tags)| (*name, tags.as_slice()))
+        .collect();
+
+    let mut mocks = vec![mock_get_catalog_services(server, &services).await];
+    for (name, instances) in CONSUL_SERVICES {
+        mocks.push(mock_get_service_instances(server, name, instances, false).await);
+    }
+    mocks
+}
+
+/// Mocks the catalog services endpoint with the given services and their tags.
+pub async fn mock_get_catalog_services(
+    server: &mut ServerGuard,
+    services: &[(&str, &[&str])],
+) -> mockito::Mock {
+    let body: serde_json::Map<String, serde_json::Value> = services
+        .iter()
+        .map(|(name, tags)| (name.to_string(), serde_json::json!(tags)))
+        .collect();
+
     server
-        .mock("GET", mockito::Matcher::Any)
+        .mock("GET", "/v1/catalog/services")
+        .match_query(mockito::Matcher::Any)
         .with_status(200)
         .with_header("Content-Type", "application/json")
-        .with_body(
-            r#"
-            {
-                "consul": [],
-                "redis": [],
-                "app-rev-mr1": [
-                    "external-dns.id1.hostname=example.com",
-                    "external-dns.id1.type=A",
-                    "external-dns.id1.value=192.168.1.100",
-                    "external-dns.id1.ttl=300",
-                    "external-dns.id1.enable=true"
-                ],
-                "app-rev-mr2": [
-                    "external-dns.id2.hostname=example.com",
-                    "external-dns.id2.type=A",
-                    "external-dns.id2.value=192.168.1.101",
-                    "external-dns.id2.ttl=300",
-                    "external-dns.id2.enable=true"
-                ],
-                "app-rev-mr3": [
-                    "external-dns.id3.hostname=example.com",
-                    "external-dns.id3.type=A",
-                    "external-dns.id3.value=192.168.1.102",
-                    "external-dns.id3.ttl=300",
-                    "external-dns.id3.enable=false"
-                ],
-                "app-rev-mr4": [
-                    "external-dns.id4.hostname=example.com",
-                    "external-dns.id4.type=A",
-                    "external-dns.id4.value=192.168.1.103",
-                    "external-dns.id4.ttl=300",
-                    "external-dns.id4.enable=true"
-                ]
-            }"#,
+        .with_header("X-Consul-Index", "10")
+        .with_body(serde_json::Value::Object(body).to_string())
+        .create_async()
+        .await
+}
+
+/// Mocks the health endpoint of a service returning one instance per tag set. If `passing` is
+/// set, the mock only matches requests asking for passing instances.
+pub async fn mock_get_service_instances(
+    server: &mut ServerGuard,
+    service_name: &str,
+    instances: &[&[&str]],
+    passing: bool,
+) -> mockito::Mock {
+    let body: Vec<serde_json::Value> = instances
+        .iter()
+        .map(|tags| serde_json::json!({ "Service": { "Service": service_name, "Tags": tags } }))
+        .collect();
+
+    let query = if passing {
+        mockito::Matcher::UrlEncoded("passing".into(), "true".into())
+    } else {
+        mockito::Matcher::Any
+    };
+
+    server
+        .mock(
+            "GET",
+            format!("/v1/health/service/{}", service_name).as_str(),
         )
+        .match_query(query)
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("X-Consul-Index", "10")
+        .with_body(serde_json::Value::Array(body).to_string())
+        .create_async()
+        .await
+}
+
+/// Mocks the health endpoint of a service returning one instance per metadata map.
+pub async fn mock_get_service_instances_with_meta(
+    server: &mut ServerGuard,
+    service_name: &str,
+    instances: &[&[(&str, &str)]],
+) -> mockito::Mock {
+    let body: Vec<serde_json::Value> = instances
+        .iter()
+        .map(|meta| {
+            let meta: serde_json::Map<String, serde_json::Value> = meta
+                .iter()
+                .map(|(key, value)| (key.to_string(), serde_json::json!(value)))
+                .collect();
+            serde_json::json!({ "Service": { "Service": service_name, "Tags": [], "Meta": meta } })
+        })
+        .collect();
+
+    server
+        .mock(
+            "GET",
+            format!("/v1/health/service/{}", service_name).as_str(),
+        )
+        .match_query(mockito::Matcher::Any)
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("X-Consul-Index", "10")
+        .with_body(serde_json::Value::Array(body).to_string())
+        .create_async()
+        .await
+}
+
+/// Mocks a recursive read of the given KV prefix returning the given keys and their values.
+pub async fn mock_get_kv_prefix(
+    server: &mut ServerGuard,
+    prefix: &str,
+    entries: &[(&str, &str)],
+) -> mockito::Mock {
+    use base64::prelude::{Engine as _, BASE64_STANDARD};
+
+    let body: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|(key, value)| {
+            serde_json::json!({
+                "Key": format!("{}{}", prefix, key),
+                "Value": BASE64_STANDARD.encode(value),
+            })
+        })
+        .collect();
+
+    server
+        .mock("GET", format!("/v1/kv/{}", prefix).as_str())
+        .match_query(mockito::Matcher::UrlEncoded(
+            "recurse".into(),
+            "true".into(),
+        ))
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("X-Consul-Index", "10")
+        .with_body(serde_json::Value::Array(body).to_string())
+        .create_async()
+        .await
+}
+
+/// Mocks the list of config entries of the given kind.
+pub async fn mock_get_config_entries(
+    server: &mut ServerGuard,
+    kind: &str,
+    entries: serde_json::Value,
+) -> mockito::Mock {
+    server
+        .mock("GET", format!("/v1/config/{}", kind).as_str())
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("X-Consul-Index", "10")
+        .with_body(entries.to_string())
+        .create_async()
+        .await
+}
+
+/// Mocks the health endpoint of a gateway returning one instance per service address.
+pub async fn mock_get_gateway_instances(
+    server: &mut ServerGuard,
+    gateway_name: &str,
+    addresses: &[&str],
+) -> mockito::Mock {
+    let body: Vec<serde_json::Value> = addresses
+        .iter()
+        .map(|address| {
+            serde_json::json!({
+                "Node": { "Address": "10.0.0.1" },
+                "Service": { "Service": gateway_name, "Address": address },
+            })
+        })
+        .collect();
+
+    server
+        .mock(
+            "GET",
+            format!("/v1/health/service/{}", gateway_name).as_str(),
+        )
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(serde_json::Value::Array(body).to_string())
+        .create_async()
+        .await
+}
+
+/// Mocks the Consul state holding the given records keyed by their ID in the DNS provider.
+pub async fn mock_get_state(server: &mut ServerGuard, state: serde_json::Value) -> mockito::Mock {
+    use base64::prelude::{Engine as _, BASE64_STANDARD};
+
+    let body = serde_json::json!([{
+        "Key": "consul_external_dns/state",
+        "Value": BASE64_STANDARD.encode(state.to_string()),
+    }]);
+
+    server
+        .mock("GET", "/v1/kv/consul_external_dns/state")
+        .match_query(mockito::Matcher::Any)
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("X-Consul-Index", "10")
+        .with_body(body.to_string())
         .create_async()
         .await
 }
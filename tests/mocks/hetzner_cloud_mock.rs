@@ -0,0 +1,86 @@
+use mockito::{Matcher, ServerGuard};
+
+/// Mocks the creation of an RRset that already exists, which Hetzner Cloud rejects with a
+/// `uniqueness_error`.
+pub async fn mock_create_existing_rrset(server: &mut ServerGuard, zone: &str) -> mockito::Mock {
+    server
+        .mock("POST", format!("/zones/{}/rrsets", zone).as_str())
+        .with_status(409)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"error": {"code": "uniqueness_error", "message": "RRset exists"}}"#)
+        .create_async()
+        .await
+}
+
+/// Mocks a single page listing the given RRsets of a zone.
+pub async fn mock_list_rrsets(
+    server: &mut ServerGuard,
+    zone: &str,
+    rrsets: serde_json::Value,
+) -> mockito::Mock {
+    server
+        .mock("GET", format!("/zones/{}/rrsets", zone).as_str())
+        .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(
+            serde_json::json!({
+                "rrsets": rrsets,
+                "meta": { "pagination": { "next_page": null } },
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await
+}
+
+/// Mocks the GET request of an RRset with the given TTL.
+pub async fn mock_get_rrset(
+    server: &mut ServerGuard,
+    zone: &str,
+    name: &str,
+    type_: &str,
+    ttl: Option<i32>,
+) -> mockito::Mock {
+    server
+        .mock(
+            "GET",
+            format!("/zones/{}/rrsets/{}/{}", zone, name, type_).as_str(),
+        )
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(
+            serde_json::json!({
+                "rrset": { "id": format!("{}/{}", name, type_), "ttl": ttl, "records": [] }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await
+}
+
+/// Mocks an action on an RRset, only matching requests with the given JSON body.
+pub async fn mock_rrset_action(
+    server: &mut ServerGuard,
+    zone: &str,
+    name: &str,
+    type_: &str,
+    action: &str,
+    body: serde_json::Value,
+) -> mockito::Mock {
+    server
+        .mock(
+            "POST",
+            format!(
+                "/zones/{}/rrsets/{}/{}/actions/{}",
+                zone, name, type_, action
+            )
+            .as_str(),
+        )
+        .match_body(Matcher::Json(body))
+        .with_status(201)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"action": {"id": 1, "status": "running"}}"#)
+        .create_async()
+        .await
+}
@@ -39,6 +39,19 @@ pub async fn mock_get_dns_records(
         .await
 }
 
+/// Mocks the GET request to retrieve a zone by its ID in Hetzner's API.
+pub async fn mock_get_zone(server: &mut ServerGuard, zone_id: &str, name: &str) -> mockito::Mock {
+    server
+        .mock("GET", format!("/zones/{}", zone_id).as_str())
+        .with_status(200)
+        .with_body(format!(
+            r#"{{"zone": {{"id": "{}", "name": "{}"}}}}"#,
+            zone_id, name
+        ))
+        .create_async()
+        .await
+}
+
 fn get_expected_body(dns_record: &DnsRecord) -> String {
     match dns_record.ttl {
         Some(ttl) => {
@@ -1,2 +1,4 @@
 pub mod consul_mock;
+pub mod hetzner_cloud_mock;
 pub mod hetzner_mock;
+pub mod nomad_mock;
@@ -0,0 +1,45 @@
+use mockito::{Matcher, ServerGuard};
+
+/// Mocks the Nomad services endpoint with a single service in the default namespace and its
+/// registrations, one per tag set.
+pub async fn mock_get_nomad_service(
+    server: &mut ServerGuard,
+    service_name: &str,
+    registrations: &[&[&str]],
+) -> Vec<mockito::Mock> {
+    let tags: Vec<&str> = registrations.concat();
+    let services = serde_json::json!([{
+        "Namespace": "default",
+        "Services": [{ "ServiceName": service_name, "Tags": tags }],
+    }]);
+    let registrations: Vec<serde_json::Value> = registrations
+        .iter()
+        .map(|tags| {
+            serde_json::json!({
+                "ServiceName": service_name,
+                "Namespace": "default",
+                "Tags": tags,
+            })
+        })
+        .collect();
+
+    vec![
+        server
+            .mock("GET", "/v1/services")
+            .match_query(Matcher::UrlEncoded("namespace".into(), "*".into()))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_header("X-Nomad-Index", "42")
+            .with_body(services.to_string())
+            .create_async()
+            .await,
+        server
+            .mock("GET", format!("/v1/service/{}", service_name).as_str())
+            .match_query(Matcher::UrlEncoded("namespace".into(), "default".into()))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::Value::Array(registrations).to_string())
+            .create_async()
+            .await,
+    ]
+}